@@ -0,0 +1,387 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use awsl::core::acme::{self, Renewal, RenewOptions};
+use awsl::core::cert::{self, CheckOptions};
+use awsl::core::config::{self, Config};
+use awsl::core::error::{Error, Result};
+use awsl::core::interface::{OverwritePolicy, Registry, WebRegistry};
+use awsl::core::lower;
+use awsl::core::nginx::{NginxHttpConfig, NginxStreamConfig};
+use awsl::core::stream::StreamRegistry;
+
+pub const USAGE: &str = "\
+usage: awsl <command> [args]
+
+commands:
+    check <file>                   validate a config and exit non-zero on errors,
+                                   then inspect the certificates of HTTPS hosts
+    render <file> [-o <out.conf>]  render the nginx http {} block, followed by
+                                   the stream {} block if there are stream servers
+    dump <file>                    print the config as normalized YAML
+    diff <file> <existing.conf>    compare the rendered config with an existing one
+    acme <file>                    obtain or renew the certificates of ACME templates,
+                                   and create the self-signed one that catch-all servers
+                                   and not yet issued hosts show
+    help                           show this message
+
+options:
+    --policy <policy>  what to do when two servers claim the same route:
+                       error (default), ignore, overwrite or warn
+
+<file> may be `-` to read the config from stdin.
+
+Catch-all servers and ACME hosts not issued yet show the self-signed certificate
+under certificates.fallback, which only `awsl acme` creates: run it before loading
+a rendered config into nginx for the first time.
+
+exit status: 0 on success, 1 on invalid config or differences, 2 on usage errors";
+
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Check { config: String, policy: OverwritePolicy },
+    Render { config: String, output: Option<String>, policy: OverwritePolicy },
+    Dump { config: String },
+    Diff { config: String, existing: String, policy: OverwritePolicy },
+    Acme { config: String },
+    Help,
+}
+
+fn parse_policy(s: &str) -> Result<OverwritePolicy, String> {
+    match s {
+        "error" => Ok(OverwritePolicy::Error),
+        "ignore" => Ok(OverwritePolicy::Ignore),
+        "overwrite" => Ok(OverwritePolicy::Overwrite),
+        "warn" => Ok(OverwritePolicy::Warn),
+        _ => Err(format!("unknown policy {}", s)),
+    }
+}
+
+pub fn parse_args(args: &[String]) -> Result<Command, String> {
+    let mut positional = Vec::new();
+    let mut output = None;
+    let mut policy = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-o" | "--output" => match iter.next() {
+                Some(v) => output = Some(v.clone()),
+                None => return Err(format!("{} requires a value", arg)),
+            },
+            "--policy" => match iter.next() {
+                Some(v) => policy = Some(parse_policy(v)?),
+                None => return Err(format!("{} requires a value", arg)),
+            },
+            "-" => positional.push(arg.clone()),
+            s if s.starts_with('-') => return Err(format!("unknown option {}", s)),
+            _ => positional.push(arg.clone()),
+        }
+    }
+
+    let (command, rest) = match positional.split_first() {
+        Some((c, rest)) => (c.as_str(), rest),
+        None => return Err("missing command".to_owned()),
+    };
+    if output.is_some() && command != "render" {
+        return Err(format!("-o is not supported by {}", command));
+    }
+    if policy.is_some() && (command == "dump" || command == "acme" || command == "help") {
+        return Err(format!("--policy is not supported by {}", command));
+    }
+    let policy = policy.unwrap_or(OverwritePolicy::Error);
+    match (command, rest) {
+        ("help", []) => Ok(Command::Help),
+        ("check", [config]) => Ok(Command::Check { config: config.clone(), policy }),
+        ("render", [config]) => Ok(Command::Render { config: config.clone(), output, policy }),
+        ("dump", [config]) => Ok(Command::Dump { config: config.clone() }),
+        ("diff", [config, existing]) => Ok(Command::Diff { config: config.clone(), existing: existing.clone(), policy }),
+        ("acme", [config]) => Ok(Command::Acme { config: config.clone() }),
+        ("help", _) | ("check", _) | ("render", _) | ("dump", _) | ("diff", _) | ("acme", _) =>
+            Err(format!("wrong number of arguments for {}", command)),
+        _ => Err(format!("unknown command {}", command)),
+    }
+}
+
+fn read_input(path: &str) -> Result<String> {
+    let io_error = |error| Error::Io { path: path.to_owned(), error };
+    if path == "-" {
+        let mut s = String::new();
+        io::stdin().read_to_string(&mut s).map_err(io_error)?;
+        Ok(s)
+    } else {
+        fs::read_to_string(path).map_err(io_error)
+    }
+}
+
+fn load_config(path: &str) -> Result<Config> {
+    let cfg: Config = serde_yaml::from_str(&read_input(path)?)?;
+    config::validate(&cfg)?;
+    Ok(cfg)
+}
+
+fn build_registry(cfg: &Config, policy: OverwritePolicy) -> Result<Registry> {
+    let mut reg: Registry = Default::default();
+    lower::lower(cfg, &mut reg, policy)?;
+    reg.normalize();
+    for record in reg.get_overwrite_log().iter().filter(|r| r.is_warning()) {
+        eprintln!("warning: {}", record);
+    }
+    Ok(reg)
+}
+
+fn build_streams(cfg: &Config, web: &Registry) -> Result<StreamRegistry> {
+    let mut reg: StreamRegistry = Default::default();
+    lower::lower_streams(cfg, &mut reg)?;
+    lower::lower_passthrough(web, &mut reg)?;
+    Ok(reg)
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+/// Prints one line per inspected certificate, and its findings as warnings.
+fn report_certificates(cfg: &Config, reg: &Registry) {
+    let options = CheckOptions {
+        directory: cfg.certificates.directory.clone(),
+        window_days: cfg.certificates.expiry_window,
+        now: now(),
+    };
+    for check in cert::check_certificates(reg, &options) {
+        println!("{}", check);
+        for finding in &check.findings {
+            eprintln!("warning: {}: {}", check.host, finding);
+        }
+    }
+    warn_missing_self_signed(cfg);
+}
+
+/// Warns when the rendered config points at a self-signed certificate that
+/// `awsl acme` has not created yet, as nginx would refuse to load it.
+fn warn_missing_self_signed(cfg: &Config) {
+    let fallback = cfg.certificates.self_signed().certificate;
+    if cfg.uses_self_signed() && !fallback.is_file() {
+        eprintln!("warning: self-signed certificate {} is missing; run `awsl acme` before loading the config", fallback.display());
+    }
+}
+
+fn render(path: &str, policy: OverwritePolicy) -> Result<String> {
+    let cfg = load_config(path)?;
+    let web = build_registry(&cfg, policy)?;
+    warn_missing_self_signed(&cfg);
+    let mut out = web.to_nginx_http_config()?;
+    let streams = build_streams(&cfg, &web)?;
+    if !streams.is_empty() {
+        out += "\n";
+        out += &streams.to_nginx_stream_config()?;
+    }
+    Ok(out)
+}
+
+pub fn run(cmd: &Command) -> Result<i32> {
+    match cmd {
+        Command::Help => {
+            println!("{}", USAGE);
+        },
+        Command::Check { config, policy } => {
+            let cfg = load_config(config)?;
+            let reg = build_registry(&cfg, *policy)?;
+            reg.to_nginx_http_config()?;
+            build_streams(&cfg, &reg)?.to_nginx_stream_config()?;
+            report_certificates(&cfg, &reg);
+            println!("{}: ok", config);
+        },
+        Command::Render { config, output, policy } => {
+            let out = render(config, *policy)?;
+            match output {
+                Some(file) => fs::write(file, out)
+                    .map_err(|error| Error::Io { path: file.clone(), error })?,
+                None => io::stdout().write_all(out.as_bytes())
+                    .map_err(|error| Error::Io { path: "<stdout>".to_owned(), error })?,
+            }
+        },
+        Command::Dump { config } => {
+            let cfg: Config = serde_yaml::from_str(&read_input(config)?)?;
+            print!("{}", serde_yaml::to_string(&cfg)?);
+        },
+        Command::Diff { config, existing, policy } => {
+            let out = render(config, *policy)?;
+            let old = read_input(existing)?;
+            if old != out {
+                print!("{}", unified_diff(existing, &old, "rendered", &out));
+                return Ok(EXIT_FAILURE);
+            }
+        },
+        Command::Acme { config } => {
+            let cfg = load_config(config)?;
+            if let Some(path) = acme::ensure_self_signed(&cfg)? {
+                println!("{}: created", path.display());
+            }
+            let options = RenewOptions { now: now(), poll_interval: Duration::from_secs(2) };
+            let mut failed = false;
+            for (host, result) in acme::renew(&cfg, &options)? {
+                match result {
+                    Ok(Renewal::Current { not_after_date }) => println!("{}: current, expires {}", host, not_after_date),
+                    Ok(Renewal::Issued { not_after_date }) => println!("{}: issued, expires {}", host, not_after_date),
+                    Err(err) => {
+                        eprintln!("awsl: {}: {}", host, err);
+                        failed = true;
+                    },
+                }
+            }
+            if failed {
+                return Ok(EXIT_FAILURE);
+            }
+        },
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+enum Edit<'a> {
+    Keep(&'a str),
+    Remove(&'a str),
+    Add(&'a str),
+}
+
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Edit<'a>> {
+    // plain LCS table; config files are small enough for O(n*m)
+    let (n, m) = (old.len(), new.len());
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut edits = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old[i] == new[j] {
+            edits.push(Edit::Keep(old[i]));
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            edits.push(Edit::Remove(old[i]));
+            i += 1;
+        } else {
+            edits.push(Edit::Add(new[j]));
+            j += 1;
+        }
+    }
+    edits
+}
+
+const DIFF_CONTEXT: usize = 3;
+
+/// Formats the difference between two texts as a unified diff.
+pub fn unified_diff(old_name: &str, old: &str, new_name: &str, new: &str) -> String {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let edits = diff_lines(&old_lines, &new_lines);
+
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    let changed: Vec<usize> = edits.iter().enumerate()
+        .filter(|(_, e)| !matches!(e, Edit::Keep(_)))
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut k = 0;
+    while k < changed.len() {
+        // grow the hunk while the next change is within the shared context
+        let start = changed[k].saturating_sub(DIFF_CONTEXT);
+        let mut last = changed[k];
+        while k + 1 < changed.len() && changed[k + 1] <= last + 2 * DIFF_CONTEXT {
+            k += 1;
+            last = changed[k];
+        }
+        let end = (last + DIFF_CONTEXT + 1).min(edits.len());
+        k += 1;
+
+        let (mut old_start, mut new_start) = (1, 1);
+        for e in &edits[..start] {
+            match e {
+                Edit::Keep(_) => { old_start += 1; new_start += 1; },
+                Edit::Remove(_) => old_start += 1,
+                Edit::Add(_) => new_start += 1,
+            }
+        }
+        let mut body = String::new();
+        let (mut old_len, mut new_len) = (0, 0);
+        for e in &edits[start..end] {
+            match e {
+                Edit::Keep(l) => { body += &format!(" {}\n", l); old_len += 1; new_len += 1; },
+                Edit::Remove(l) => { body += &format!("-{}\n", l); old_len += 1; },
+                Edit::Add(l) => { body += &format!("+{}\n", l); new_len += 1; },
+            }
+        }
+        out += &format!("@@ -{},{} +{},{} @@\n{}", old_start, old_len, new_start, new_len, body);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|x| x.to_owned()).collect()
+    }
+
+    #[test]
+    fn cli_parse_commands() {
+        let policy = OverwritePolicy::Error;
+        assert_eq!(parse_args(&args("check a.yml")), Ok(Command::Check { config: "a.yml".to_owned(), policy }));
+        assert_eq!(parse_args(&args("render - -o out.conf")), Ok(Command::Render { config: "-".to_owned(), output: Some("out.conf".to_owned()), policy }));
+        assert_eq!(parse_args(&args("render a.yml --policy warn")), Ok(Command::Render { config: "a.yml".to_owned(), output: None, policy: OverwritePolicy::Warn }));
+        assert_eq!(parse_args(&args("dump a.yml")), Ok(Command::Dump { config: "a.yml".to_owned() }));
+        assert_eq!(parse_args(&args("diff a.yml nginx.conf --policy ignore")), Ok(Command::Diff { config: "a.yml".to_owned(), existing: "nginx.conf".to_owned(), policy: OverwritePolicy::Ignore }));
+        assert_eq!(parse_args(&args("acme a.yml")), Ok(Command::Acme { config: "a.yml".to_owned() }));
+        assert_eq!(parse_args(&args("--help")), Ok(Command::Help));
+    }
+
+    #[test]
+    fn cli_parse_errors() {
+        assert!(parse_args(&args("")).is_err());
+        assert!(parse_args(&args("frobnicate a.yml")).is_err());
+        assert!(parse_args(&args("check")).is_err());
+        assert!(parse_args(&args("check a.yml b.yml")).is_err());
+        assert!(parse_args(&args("check a.yml -o x")).is_err());
+        assert!(parse_args(&args("render a.yml -o")).is_err());
+        assert!(parse_args(&args("render a.yml --bogus")).is_err());
+        assert!(parse_args(&args("render a.yml --policy maybe")).is_err());
+        assert!(parse_args(&args("dump a.yml --policy warn")).is_err());
+    }
+
+    #[test]
+    fn cli_unified_diff() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+        let new = "a\nb\nc\nd\nE\nf\ng\nh\ni\nj\nk\n";
+        assert_eq!(unified_diff("old", old, "new", new), "\
+--- old
++++ new
+@@ -2,9 +2,10 @@
+ b
+ c
+ d
+-e
++E
+ f
+ g
+ h
+ i
+ j
++k
+");
+        assert_eq!(unified_diff("old", old, "new", old), "--- old\n+++ new\n");
+    }
+}
@@ -0,0 +1,401 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::digest::{digest, SHA256};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use serde_json::{json, Value};
+use super::cert::read_certificate;
+use super::config::{Config, ConfigAcme};
+use super::error::{Error, Result};
+
+/*
+
+A minimal RFC 8555 client: one ES256 account key per state directory,
+one order per host, HTTP-01 challenges only.
+
+<state>/
+    account.pk8
+    challenges/<token>
+    <host>/fullchain.pem
+    <host>/privkey.pem
+
+*/
+
+const DAY: i64 = 24 * 60 * 60;
+
+fn b64(data: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(data)
+}
+
+fn acme_error(message: impl Into<String>) -> Error {
+    Error::Acme(message.into())
+}
+
+fn io_error(path: &Path) -> impl Fn(std::io::Error) -> Error + '_ {
+    move |error| Error::Io { path: path.display().to_string(), error }
+}
+
+/// Writes `data` readable by the owner only, creating parent directories.
+fn write_private(path: &Path, data: &[u8]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(io_error(dir))?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path).and_then(|mut f| f.write_all(data)).map_err(io_error(path))
+}
+
+/// The ES256 key identifying an ACME account.
+pub struct Account {
+    key: EcdsaKeyPair,
+    pkcs8: Vec<u8>,
+}
+
+impl Account {
+    pub fn generate() -> Result<Self> {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+            .map_err(|_| acme_error("cannot generate an account key"))?;
+        Self::from_pkcs8(pkcs8.as_ref())
+    }
+
+    pub fn from_pkcs8(pkcs8: &[u8]) -> Result<Self> {
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8, &SystemRandom::new())
+            .map_err(|e| acme_error(format!("invalid account key: {}", e)))?;
+        Ok(Account { key, pkcs8: pkcs8.to_vec() })
+    }
+
+    /// Loads the account key at `path`, creating it on first use.
+    pub fn load_or_generate(path: &Path) -> Result<Self> {
+        match fs::read(path) {
+            Ok(pkcs8) => Self::from_pkcs8(&pkcs8),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let account = Self::generate()?;
+                write_private(path, &account.pkcs8)?;
+                Ok(account)
+            },
+            Err(e) => Err(io_error(path)(e)),
+        }
+    }
+
+    pub fn jwk(&self) -> Value {
+        // uncompressed point: 0x04 || x || y
+        let point = self.key.public_key().as_ref();
+        json!({ "crv": "P-256", "kty": "EC", "x": b64(&point[1..33]), "y": b64(&point[33..65]) })
+    }
+
+    /// RFC 7638 thumbprint; serde_json keeps object keys sorted, as required.
+    pub fn thumbprint(&self) -> String {
+        b64(digest(&SHA256, self.jwk().to_string().as_bytes()).as_ref())
+    }
+
+    pub fn key_authorization(&self, token: &str) -> String {
+        format!("{}.{}", token, self.thumbprint())
+    }
+
+    fn sign(&self, data: &[u8]) -> Result<String> {
+        let signature = self.key.sign(&SystemRandom::new(), data)
+            .map_err(|_| acme_error("cannot sign request"))?;
+        Ok(b64(signature.as_ref()))
+    }
+}
+
+/// A certificate chain and its private key, both PEM encoded.
+pub struct IssuedCertificate {
+    pub chain: String,
+    pub key: String,
+}
+
+pub struct AcmeClient {
+    agent: ureq::Agent,
+    account: Account,
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+    nonce: Option<String>,
+    kid: Option<String>,
+    /// How long to wait between polls of a pending authorization or order.
+    pub poll_interval: Duration,
+    pub poll_attempts: u32,
+}
+
+impl AcmeClient {
+    /// Fetches the directory at `url`; nothing is sent on behalf of
+    /// `account` until `register`.
+    pub fn new(url: &str, account: Account) -> Result<Self> {
+        let agent = ureq::AgentBuilder::new().timeout(Duration::from_secs(30)).build();
+        let directory: Value = agent.get(url).call()
+            .map_err(|e| acme_error(format!("{}: {}", url, e)))?
+            .into_json()
+            .map_err(|e| acme_error(format!("{}: {}", url, e)))?;
+        let endpoint = |name: &str| directory[name].as_str().map(str::to_owned)
+            .ok_or_else(|| acme_error(format!("directory {} has no {}", url, name)));
+        Ok(AcmeClient {
+            new_nonce: endpoint("newNonce")?,
+            new_account: endpoint("newAccount")?,
+            new_order: endpoint("newOrder")?,
+            agent,
+            account,
+            nonce: None,
+            kid: None,
+            poll_interval: Duration::from_secs(2),
+            poll_attempts: 30,
+        })
+    }
+
+    fn next_nonce(&mut self) -> Result<String> {
+        if let Some(nonce) = self.nonce.take() {
+            return Ok(nonce);
+        }
+        let response = self.agent.head(&self.new_nonce).call()
+            .map_err(|e| acme_error(format!("{}: {}", self.new_nonce, e)))?;
+        response.header("Replay-Nonce").map(str::to_owned)
+            .ok_or_else(|| acme_error("newNonce returned no Replay-Nonce"))
+    }
+
+    /// Sends a JWS signed request; `None` is a POST-as-GET.
+    fn post(&mut self, url: &str, payload: Option<&Value>) -> Result<ureq::Response> {
+        let mut retried = false;
+        loop {
+            let mut protected = json!({ "alg": "ES256", "nonce": self.next_nonce()?, "url": url });
+            match &self.kid {
+                Some(kid) => protected["kid"] = json!(kid),
+                None => protected["jwk"] = self.account.jwk(),
+            }
+            let protected = b64(protected.to_string().as_bytes());
+            let payload = payload.map(|p| b64(p.to_string().as_bytes())).unwrap_or_default();
+            let signature = self.account.sign(format!("{}.{}", protected, payload).as_bytes())?;
+            let body = json!({ "protected": protected, "payload": payload, "signature": signature });
+
+            let response = match self.agent.post(url).set("Content-Type", "application/jose+json").send_string(&body.to_string()) {
+                Ok(r) | Err(ureq::Error::Status(_, r)) => r,
+                Err(e) => return Err(acme_error(format!("{}: {}", url, e))),
+            };
+            if let Some(nonce) = response.header("Replay-Nonce") {
+                self.nonce = Some(nonce.to_owned());
+            }
+            let status = response.status();
+            if status < 400 {
+                return Ok(response);
+            }
+
+            let problem: Value = response.into_json().unwrap_or(Value::Null);
+            if !retried && problem["type"] == "urn:ietf:params:acme:error:badNonce" {
+                retried = true;
+                continue;
+            }
+            return Err(acme_error(format!("{} returned {}: {}", url, status, problem["detail"].as_str().unwrap_or("no details"))));
+        }
+    }
+
+    /// Like `post`, returning the JSON body and the `Location` header.
+    fn post_json(&mut self, url: &str, payload: Option<&Value>) -> Result<(Value, Option<String>)> {
+        let response = self.post(url, payload)?;
+        let location = response.header("Location").map(str::to_owned);
+        let body = response.into_json().map_err(|e| acme_error(format!("{}: {}", url, e)))?;
+        Ok((body, location))
+    }
+
+    /// Re-reads `url` until its status is neither pending nor processing.
+    fn poll(&mut self, url: &str) -> Result<Value> {
+        for _ in 0..self.poll_attempts {
+            let (body, _) = self.post_json(url, None)?;
+            if body["status"] != "pending" && body["status"] != "processing" {
+                return Ok(body);
+            }
+            thread::sleep(self.poll_interval);
+        }
+        Err(acme_error(format!("{} is still pending", url)))
+    }
+
+    /// Creates the account, or looks up the existing one for this key.
+    pub fn register(&mut self, email: Option<&str>) -> Result<()> {
+        let mut payload = json!({ "termsOfServiceAgreed": true });
+        if let Some(email) = email {
+            payload["contact"] = json!([format!("mailto:{}", email)]);
+        }
+        let url = self.new_account.clone();
+        let (_, location) = self.post_json(&url, Some(&payload))?;
+        self.kid = Some(location.ok_or_else(|| acme_error("newAccount returned no Location"))?);
+        Ok(())
+    }
+
+    fn authorize(&mut self, order: &Value, challenges: &Path, tokens: &mut Vec<String>) -> Result<()> {
+        let authorizations: Vec<String> = order["authorizations"].as_array()
+            .map(|a| a.iter().filter_map(|u| u.as_str().map(str::to_owned)).collect())
+            .unwrap_or_default();
+        for url in authorizations {
+            let (authz, _) = self.post_json(&url, None)?;
+            if authz["status"] == "valid" {
+                continue;
+            }
+            let name = authz["identifier"]["value"].as_str().unwrap_or("?").to_owned();
+            let challenge = authz["challenges"].as_array()
+                .and_then(|c| c.iter().find(|c| c["type"] == "http-01"))
+                .ok_or_else(|| acme_error(format!("no http-01 challenge offered for {}", name)))?;
+            let token = challenge["token"].as_str().unwrap_or_default().to_owned();
+            let challenge_url = challenge["url"].as_str().unwrap_or_default().to_owned();
+            // tokens come from the server, so keep them from escaping the challenge directory
+            if token.is_empty() || !token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                return Err(acme_error(format!("invalid challenge token {:?} for {}", token, name)));
+            }
+
+            fs::create_dir_all(challenges).map_err(io_error(challenges))?;
+            let file = challenges.join(&token);
+            fs::write(&file, self.account.key_authorization(&token)).map_err(io_error(&file))?;
+            tokens.push(token);
+
+            self.post_json(&challenge_url, Some(&json!({})))?;
+            let authz = self.poll(&url)?;
+            if authz["status"] != "valid" {
+                let detail = authz["challenges"].as_array()
+                    .and_then(|c| c.iter().find_map(|c| c["error"]["detail"].as_str()))
+                    .unwrap_or("no details");
+                return Err(acme_error(format!("authorization of {} failed: {}", name, detail)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Orders a certificate for `names`, answering HTTP-01 challenges by
+    /// writing tokens into `challenges`, which must be served at
+    /// `/.well-known/acme-challenge/`.
+    pub fn issue(&mut self, names: &[String], challenges: &Path) -> Result<IssuedCertificate> {
+        let identifiers: Vec<Value> = names.iter().map(|n| json!({ "type": "dns", "value": n })).collect();
+        let url = self.new_order.clone();
+        let (order, location) = self.post_json(&url, Some(&json!({ "identifiers": identifiers })))?;
+        let order_url = location.ok_or_else(|| acme_error("newOrder returned no Location"))?;
+
+        let mut tokens = Vec::new();
+        let authorized = self.authorize(&order, challenges, &mut tokens);
+        for token in tokens {
+            let _ = fs::remove_file(challenges.join(token));
+        }
+        authorized?;
+
+        let key = rcgen::KeyPair::generate().map_err(|e| acme_error(e.to_string()))?;
+        let csr = rcgen::CertificateParams::new(names.to_vec())
+            .and_then(|params| params.serialize_request(&key))
+            .map_err(|e| acme_error(e.to_string()))?;
+        let finalize = order["finalize"].as_str().unwrap_or_default().to_owned();
+        self.post_json(&finalize, Some(&json!({ "csr": b64(csr.der()) })))?;
+
+        let order = self.poll(&order_url)?;
+        let certificate = match (order["status"].as_str(), order["certificate"].as_str()) {
+            (Some("valid"), Some(url)) => url.to_owned(),
+            (status, _) => return Err(acme_error(format!("order {} ended as {}", order_url, status.unwrap_or("?")))),
+        };
+        let chain = self.post(&certificate, None)?.into_string()
+            .map_err(|e| acme_error(format!("{}: {}", certificate, e)))?;
+        Ok(IssuedCertificate { chain, key: key.serialize_pem() })
+    }
+}
+
+/// How `renew` decides what is due and how it waits for the server.
+#[derive(Debug, Clone)]
+pub struct RenewOptions {
+    /// Current unix time.
+    pub now: i64,
+    pub poll_interval: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Renewal {
+    /// The stored certificate is outside the renewal window.
+    Current { not_after_date: String },
+    Issued { not_after_date: String },
+}
+
+/// The hosts each ACME configuration issues certificates for, in config order.
+pub fn managed_hosts(cfg: &Config) -> Vec<(&ConfigAcme, Vec<String>)> {
+    let mut groups: Vec<(&ConfigAcme, Vec<String>)> = Vec::new();
+    for server in &cfg.servers {
+        let acme = match server.acme(cfg) {
+            Some(acme) => acme,
+            None => continue,
+        };
+        let index = match groups.iter().position(|(a, _)| *a == acme) {
+            Some(index) => index,
+            None => {
+                groups.push((acme, Vec::new()));
+                groups.len() - 1
+            },
+        };
+        let hosts = &mut groups[index].1;
+        for host in &server.host {
+            if !hosts.contains(host) {
+                hosts.push(host.clone());
+            }
+        }
+    }
+    groups
+}
+
+fn current_certificate(acme: &ConfigAcme, host: &str, now: i64) -> Option<String> {
+    let info = read_certificate(&acme.certificate_path(host)).ok()?;
+    if info.not_after - now > i64::from(acme.renew_before) * DAY && acme.key_path(host).is_file() {
+        Some(info.not_after_date)
+    } else {
+        None
+    }
+}
+
+/// Creates the self-signed certificate under `certificates.fallback` if a
+/// catch-all server or a host awaiting issuance shows it and it is missing,
+/// returning its path then.
+pub fn ensure_self_signed(cfg: &Config) -> Result<Option<PathBuf>> {
+    let tls = cfg.certificates.self_signed();
+    if !cfg.uses_self_signed() || (tls.certificate.is_file() && tls.key.is_file()) {
+        return Ok(None);
+    }
+    let issued = rcgen::generate_simple_self_signed(vec!["fallback.invalid".to_owned()])
+        .map_err(|e| acme_error(format!("cannot create a self-signed certificate: {}", e)))?;
+    write_private(&tls.key, issued.key_pair.serialize_pem().as_bytes())?;
+    fs::write(&tls.certificate, issued.cert.pem()).map_err(io_error(&tls.certificate))?;
+    Ok(Some(tls.certificate))
+}
+
+/// Issues a certificate for every managed host that has none or whose
+/// certificate expires within `renewBefore` days. Failures are reported
+/// per host; only directory and account errors abort a whole group.
+pub fn renew(cfg: &Config, options: &RenewOptions) -> Result<Vec<(String, Result<Renewal>)>> {
+    let mut results = Vec::new();
+    for (acme, hosts) in managed_hosts(cfg) {
+        let mut due = Vec::new();
+        for host in hosts {
+            match current_certificate(acme, &host, options.now) {
+                Some(not_after_date) => results.push((host, Ok(Renewal::Current { not_after_date }))),
+                None => due.push(host),
+            }
+        }
+        if due.is_empty() {
+            continue;
+        }
+
+        let account = Account::load_or_generate(&acme.account_key_path())?;
+        let mut client = AcmeClient::new(&acme.directory, account)?;
+        client.poll_interval = options.poll_interval;
+        client.register(acme.email.as_deref())?;
+        for host in due {
+            let issued = client.issue(std::slice::from_ref(&host), &acme.challenge_directory()).and_then(|cert| {
+                write_private(&acme.key_path(&host), cert.key.as_bytes())?;
+                let path = acme.certificate_path(&host);
+                fs::write(&path, cert.chain.as_bytes()).map_err(io_error(&path))?;
+                let info = read_certificate(&path).map_err(|e| acme_error(format!("{}: {}", path.display(), e)))?;
+                Ok(Renewal::Issued { not_after_date: info.not_after_date })
+            });
+            results.push((host, issued));
+        }
+    }
+    Ok(results)
+}
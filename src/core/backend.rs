@@ -0,0 +1,948 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use super::config::{is_quotable_pattern, ConfigAutoindexFormat, ConfigBackend, ConfigBalance, ConfigCatchAllAction, ConfigCompression, ConfigFileOptions,
+    ConfigForwardedHeader, ConfigProxyOptions, ConfigRewriteOptions, ConfigUpstreamServer};
+use super::interface::{BackendDescriptor, Error, Result};
+use super::nginx::indent;
+use super::stream::{passthrough_socket, StreamDescriptor};
+
+/// Rejects values that would end or open an nginx directive when inlined.
+fn directive_value<'a>(backend: &dyn BackendDescriptor, value: &'a str) -> Result<&'a str> {
+    if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == ';' || c == '{' || c == '}') {
+        return Err(Error::InvalidBackend {
+            key: backend.get_key(),
+            message: format!("{:?} cannot be used as a directive value", value),
+        });
+    }
+    Ok(value)
+}
+
+/// Quotes a header or parameter value; quotes inside cannot be escaped
+/// portably, so they are rejected.
+fn quoted_value(backend: &dyn BackendDescriptor, value: &str) -> Result<String> {
+    if value.contains(|c: char| c == '"' || c == '\\' || c.is_control()) {
+        return Err(Error::InvalidBackend {
+            key: backend.get_key(),
+            message: format!("{:?} cannot be used as a quoted value", value),
+        });
+    }
+    Ok(format!("\"{}\"", value))
+}
+
+fn quoted_pattern(backend: &dyn BackendDescriptor, pattern: &str) -> Result<String> {
+    if !is_quotable_pattern(pattern) {
+        return Err(Error::InvalidBackend {
+            key: backend.get_key(),
+            message: format!("{:?} cannot be used as a pattern", pattern),
+        });
+    }
+    Ok(format!("\"{}\"", pattern))
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value { "on" } else { "off" }
+}
+
+fn proxy_pass_target(target: &str) -> String {
+    if target.contains("://") {
+        target.to_owned()
+    } else {
+        // "host:port" and "unix:/path" both need an explicit scheme
+        format!("http://{}", target)
+    }
+}
+
+/// 64-bit FNV-1a, so generated names stay the same across runs and builds.
+fn fnv1a(data: &str) -> u64 {
+    data.bytes().fold(0xcbf29ce484222325, |hash, b| (hash ^ u64::from(b)).wrapping_mul(0x100000001b3))
+}
+
+/// An `upstream {}` block, named after its own key so that every server
+/// using the same set shares one block.
+#[derive(Debug, Clone)]
+pub struct Upstream {
+    pub servers: Vec<ConfigUpstreamServer>,
+    pub balance: ConfigBalance,
+}
+
+impl Upstream {
+    /// A bare address with default balancing is passed to proxy_pass directly.
+    fn of(target: &[ConfigUpstreamServer], balance: &ConfigBalance) -> Option<Upstream> {
+        if target.len() == 1 && target[0].is_plain() && balance.is_round_robin() {
+            None
+        } else {
+            Some(Upstream { servers: target.to_vec(), balance: balance.clone() })
+        }
+    }
+
+    pub fn name(&self) -> String {
+        format!("awsl_{:016x}", fnv1a(&self.get_key()))
+    }
+}
+
+impl BackendDescriptor for Upstream {
+    // spelled out field by field, as name() hashes it: a field added later
+    // must leave the upstreams that do not use it under their old names
+    fn get_key(&self) -> String {
+        let balance = match &self.balance {
+            ConfigBalance::RoundRobin => "roundRobin".to_owned(),
+            ConfigBalance::LeastConn => "leastConn".to_owned(),
+            ConfigBalance::IpHash => "ipHash".to_owned(),
+            ConfigBalance::Hash { key, consistent } => format!("hash {}{}", key, if *consistent { " consistent" } else { "" }),
+        };
+        let servers: Vec<String> = self.servers.iter().map(|server| {
+            let mut key = server.server.clone();
+            if let Some(weight) = server.weight {
+                key += &format!(" weight={}", weight);
+            }
+            if let Some(max_fails) = server.max_fails {
+                key += &format!(" max_fails={}", max_fails);
+            }
+            if let Some(fail_timeout) = &server.fail_timeout {
+                key += &format!(" fail_timeout={}", fail_timeout);
+            }
+            if server.backup {
+                key += " backup";
+            }
+            if server.down {
+                key += " down";
+            }
+            key
+        }).collect();
+        format!("upstream {} [{}]", balance, servers.join(", "))
+    }
+
+    fn to_backend_config(&self) -> Result<String> {
+        let mut lines = Vec::new();
+        match &self.balance {
+            ConfigBalance::RoundRobin => {},
+            ConfigBalance::LeastConn => lines.push("least_conn;".to_owned()),
+            ConfigBalance::IpHash => lines.push("ip_hash;".to_owned()),
+            ConfigBalance::Hash { key, consistent } => lines.push(format!("hash {}{};",
+                directive_value(self, key)?, if *consistent { " consistent" } else { "" })),
+        }
+        for server in &self.servers {
+            let mut line = format!("server {}", directive_value(self, &server.server)?);
+            if let Some(weight) = server.weight {
+                line += &format!(" weight={}", weight);
+            }
+            if let Some(max_fails) = server.max_fails {
+                line += &format!(" max_fails={}", max_fails);
+            }
+            if let Some(fail_timeout) = &server.fail_timeout {
+                line += &format!(" fail_timeout={}", directive_value(self, fail_timeout)?);
+            }
+            if server.backup {
+                line += " backup";
+            }
+            if server.down {
+                line += " down";
+            }
+            lines.push(line + ";");
+        }
+        Ok(format!("upstream {} {{\n{}\n}}", self.name(), indent(&lines.join("\n"), 1)))
+    }
+}
+
+/// Maps `$http_upgrade` to the `Connection` header websocket proxying
+/// needs; shared by every location with `websocket: true`.
+#[derive(Debug)]
+pub struct WebsocketUpgrade;
+
+impl BackendDescriptor for WebsocketUpgrade {
+    fn get_key(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    fn to_backend_config(&self) -> Result<String> {
+        Ok("map $http_upgrade $connection_upgrade {\n    default upgrade;\n    '' close;\n}".to_owned())
+    }
+}
+
+fn proxy_options(backend: &dyn BackendDescriptor, options: &ConfigProxyOptions) -> Result<Vec<String>> {
+    let mut lines = Vec::new();
+    if options.websocket {
+        lines.push("proxy_http_version 1.1;".to_owned());
+        lines.push("proxy_set_header Upgrade $http_upgrade;".to_owned());
+        lines.push("proxy_set_header Connection $connection_upgrade;".to_owned());
+    }
+    for header in &options.forwarded {
+        lines.push(match header {
+            ConfigForwardedHeader::For => "proxy_set_header X-Forwarded-For $proxy_add_x_forwarded_for;",
+            ConfigForwardedHeader::Proto => "proxy_set_header X-Forwarded-Proto $scheme;",
+            ConfigForwardedHeader::Host => "proxy_set_header X-Forwarded-Host $host;",
+            ConfigForwardedHeader::RealIp => "proxy_set_header X-Real-IP $remote_addr;",
+        }.to_owned());
+    }
+    for (name, value) in &options.request_headers {
+        lines.push(format!("proxy_set_header {} {};", directive_value(backend, name)?, quoted_value(backend, value)?));
+    }
+    for (directive, timeout) in [("proxy_connect_timeout", &options.connect_timeout),
+            ("proxy_read_timeout", &options.read_timeout), ("proxy_send_timeout", &options.send_timeout)] {
+        if let Some(timeout) = timeout {
+            lines.push(format!("{} {};", directive, directive_value(backend, timeout)?));
+        }
+    }
+    if let Some(buffering) = options.buffering {
+        lines.push(format!("proxy_buffering {};", on_off(buffering)));
+    }
+    if let Some(size) = &options.max_body_size {
+        lines.push(format!("client_max_body_size {};", directive_value(backend, size)?));
+    }
+    for (name, value) in &options.response_headers {
+        lines.push(format!("add_header {} {} always;", directive_value(backend, name)?, quoted_value(backend, value)?));
+    }
+    Ok(lines)
+}
+
+/// A named location answering with an empty gRPC response carrying
+/// `grpc-status`, for use as an `error_page`.
+#[derive(Debug)]
+pub struct GrpcStatus {
+    pub code: u8,
+}
+
+impl GrpcStatus {
+    pub fn name(&self) -> String {
+        format!("@awsl_grpc_{}", self.code)
+    }
+}
+
+impl BackendDescriptor for GrpcStatus {
+    fn get_key(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    fn to_backend_config(&self) -> Result<String> {
+        Ok(format!("location {} {{\n{}\n}}", self.name(), indent(&format!("\
+default_type application/grpc;
+add_header grpc-status {};
+add_header content-length 0;
+return 204;", self.code), 1)))
+    }
+}
+
+fn pass_params(backend: &dyn BackendDescriptor, directive: &str, params: &BTreeMap<String, String>) -> Result<Vec<String>> {
+    params.iter()
+        .map(|(name, value)| Ok(format!("{} {} {};", directive, directive_value(backend, name)?, quoted_value(backend, value)?)))
+        .collect()
+}
+
+fn rewrite(backend: &dyn BackendDescriptor, target: &str, code: u16, options: &ConfigRewriteOptions) -> Result<String> {
+    let target = directive_value(backend, target)?;
+    let pattern = options.pattern.as_deref().map(|p| quoted_pattern(backend, p)).transpose()?;
+    let mut lines = Vec::new();
+    if options.internal {
+        // rewrite appends the query string itself unless told not to by "?"
+        let mut replacement = target.to_owned();
+        if options.preserve_path {
+            replacement = replacement.trim_end_matches('/').to_owned() + "$uri";
+        }
+        if !options.preserve_query {
+            replacement += "?";
+        }
+        lines.push(format!("rewrite {} {} last;", pattern.as_deref().unwrap_or("^"), replacement));
+    } else {
+        let code = match (options.keep_method, code) {
+            (true, 301) => 308,
+            (true, 302) => 307,
+            (_, code) => code,
+        };
+        // $uri is decoded, so a %0d%0a in it would split the Location header;
+        // redirects take the path from the raw $request_uri instead
+        let (location, pattern) = match (options.preserve_path, options.preserve_query) {
+            (true, true) => (target.trim_end_matches('/').to_owned() + "$request_uri", pattern),
+            (true, false) if pattern.is_some() => return Err(Error::InvalidBackend {
+                key: backend.get_key(),
+                message: "preservePath takes the path from its own pattern; use captures of pattern instead".to_owned(),
+            }),
+            (true, false) => (target.trim_end_matches('/').to_owned() + "$1", Some("\"^([^?]*)\"".to_owned())),
+            (false, true) => (target.to_owned() + "$is_args$args", pattern),
+            (false, false) => (target.to_owned(), pattern),
+        };
+        let redirect = format!("return {} {};", code, location);
+        lines.push(match &pattern {
+            // return keeps the captures of the enclosing if
+            Some(pattern) => format!("if ($request_uri ~ {}) {{\n    {}\n}}", pattern, redirect),
+            None => redirect,
+        });
+    }
+    if options.pattern.is_some() {
+        lines.push("return 404;".to_owned());
+    }
+    Ok(lines.join("\n"))
+}
+
+fn file_options(backend: &dyn BackendDescriptor, options: &ConfigFileOptions) -> Result<Vec<String>> {
+    let mut lines = Vec::new();
+    if !options.index.is_empty() {
+        let index = options.index.iter().map(|f| directive_value(backend, f)).collect::<Result<Vec<_>>>()?;
+        lines.push(format!("index {};", index.join(" ")));
+    }
+    if options.autoindex {
+        lines.push("autoindex on;".to_owned());
+        if let Some(format) = options.autoindex_format {
+            lines.push(format!("autoindex_format {};", match format {
+                ConfigAutoindexFormat::Html => "html",
+                ConfigAutoindexFormat::Xml => "xml",
+                ConfigAutoindexFormat::Json => "json",
+                ConfigAutoindexFormat::Jsonp => "jsonp",
+            }));
+        }
+    }
+    if let Some(fallback) = &options.fallback {
+        lines.push(format!("try_files $uri $uri/ {};", directive_value(backend, fallback)?));
+    } else if !options.try_files.is_empty() {
+        let files = options.try_files.iter().map(|f| directive_value(backend, f)).collect::<Result<Vec<_>>>()?;
+        lines.push(format!("try_files {};", files.join(" ")));
+    }
+    for compression in &options.precompressed {
+        lines.push(match compression {
+            ConfigCompression::Gzip => "gzip_static on;",
+            ConfigCompression::Brotli => "brotli_static on;",
+        }.to_owned());
+    }
+    // one nested location per distinct value, listing its extensions
+    let mut expires: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (extension, value) in &options.expires {
+        expires.entry(value.as_str()).or_default().push(directive_value(backend, extension)?);
+    }
+    for (value, extensions) in expires {
+        lines.push(format!("location ~* \\.({})$ {{\n    expires {};\n}}", extensions.join("|"), directive_value(backend, value)?));
+    }
+    Ok(lines)
+}
+
+impl BackendDescriptor for ConfigBackend {
+    fn get_key(&self) -> String {
+        // the serialized form covers every option but leaves out defaults,
+        // so equal keys mean equal output and conflicts stay readable
+        serde_json::to_string(self).expect("backends always serialize")
+    }
+
+    fn to_backend_config(&self) -> Result<String> {
+        Ok(match self {
+            ConfigBackend::Proxy { target, balance, options } => {
+                let mut lines = vec![match Upstream::of(target, balance) {
+                    Some(upstream) => format!("proxy_pass http://{};", upstream.name()),
+                    None => format!("proxy_pass {};", proxy_pass_target(directive_value(self, &target[0].server)?)),
+                }];
+                lines.extend(proxy_options(self, options)?);
+                lines.join("\n")
+            },
+            // rendered by the stream server of the port, see as_passthrough
+            ConfigBackend::Passthrough { .. } => return Err(Error::InvalidBackend {
+                key: self.get_key(),
+                message: "passthrough cannot serve a location".to_owned(),
+            }),
+            ConfigBackend::Rewrite { target, code, options } => rewrite(self, target, *code, options)?,
+            ConfigBackend::File { path, options } => {
+                let mut lines = vec![format!("{} {};", if options.alias { "alias" } else { "root" },
+                    directive_value(self, &path.to_string_lossy())?)];
+                lines.extend(file_options(self, options)?);
+                lines.join("\n")
+            },
+            ConfigBackend::FastCgi { target, root, index, front_controller, params } => {
+                let mut lines = vec![
+                    format!("root {};", directive_value(self, &root.to_string_lossy())?),
+                    format!("index {};", directive_value(self, index)?),
+                ];
+                if let Some(front) = front_controller {
+                    lines.push(format!("try_files $uri $uri/ {}$is_args$args;", directive_value(self, front)?));
+                }
+                // the nested location keeps everything but scripts static
+                let mut script = vec![
+                    // refuse to run scripts that do not exist
+                    "try_files $fastcgi_script_name =404;".to_owned(),
+                    format!("fastcgi_pass {};", directive_value(self, target)?),
+                    "include fastcgi_params;".to_owned(),
+                ];
+                if !params.contains_key("SCRIPT_FILENAME") {
+                    script.push("fastcgi_param SCRIPT_FILENAME $document_root$fastcgi_script_name;".to_owned());
+                }
+                script.extend(pass_params(self, "fastcgi_param", params)?);
+                lines.push(format!("location ~ \\.php$ {{\n{}\n}}", indent(&script.join("\n"), 1)));
+                lines.join("\n")
+            },
+            ConfigBackend::Uwsgi { target, params, modifier1, modifier2 } => {
+                let mut lines = vec![
+                    format!("uwsgi_pass {};", directive_value(self, target)?),
+                    "include uwsgi_params;".to_owned(),
+                ];
+                lines.extend(pass_params(self, "uwsgi_param", params)?);
+                if let Some(modifier) = modifier1 {
+                    lines.push(format!("uwsgi_modifier1 {};", modifier));
+                }
+                if let Some(modifier) = modifier2 {
+                    lines.push(format!("uwsgi_modifier2 {};", modifier));
+                }
+                lines.join("\n")
+            },
+            ConfigBackend::Scgi { target, params } => {
+                let mut lines = vec![
+                    format!("scgi_pass {};", directive_value(self, target)?),
+                    "include scgi_params;".to_owned(),
+                ];
+                lines.extend(pass_params(self, "scgi_param", params)?);
+                lines.join("\n")
+            },
+            ConfigBackend::Grpc { target, errors } => {
+                let mut lines = vec![format!("grpc_pass {};", directive_value(self, target)?)];
+                if target.starts_with("grpcs://") {
+                    // send SNI, which TLS terminating gRPC servers usually need
+                    lines.push("grpc_ssl_server_name on;".to_owned());
+                }
+                let mut codes: BTreeMap<u8, Vec<String>> = BTreeMap::new();
+                for (status, code) in errors {
+                    codes.entry(*code).or_default().push(status.to_string());
+                }
+                for (code, statuses) in codes {
+                    lines.push(format!("error_page {} = {};", statuses.join(" "), GrpcStatus { code }.name()));
+                }
+                lines.join("\n")
+            },
+        })
+    }
+
+    fn get_server_blocks(&self) -> Vec<Arc<dyn BackendDescriptor>> {
+        match self {
+            ConfigBackend::Grpc { errors, .. } => {
+                let mut codes: Vec<u8> = errors.values().copied().collect();
+                codes.sort_unstable();
+                codes.dedup();
+                codes.into_iter().map(|code| Arc::new(GrpcStatus { code }) as Arc<dyn BackendDescriptor>).collect()
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    fn requires_http2(&self) -> bool {
+        matches!(self, ConfigBackend::Grpc { .. })
+    }
+
+    fn get_http_blocks(&self) -> Vec<Arc<dyn BackendDescriptor>> {
+        match self {
+            ConfigBackend::Proxy { target, balance, options } => {
+                let mut blocks: Vec<Arc<dyn BackendDescriptor>> = Vec::new();
+                if let Some(upstream) = Upstream::of(target, balance) {
+                    blocks.push(Arc::new(upstream));
+                }
+                if options.websocket {
+                    blocks.push(Arc::new(WebsocketUpgrade));
+                }
+                blocks
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    fn as_passthrough(&self) -> Option<Arc<dyn StreamDescriptor>> {
+        match self {
+            ConfigBackend::Passthrough { .. } => Some(Arc::new(StreamProxy { inner: self.clone() })),
+            _ => None,
+        }
+    }
+}
+
+/// The `proxy` backend of a stream server, or a `passthrough` backend:
+/// the same targets and balancing, passed without a scheme and with only
+/// the timeouts.
+#[derive(Debug)]
+pub struct StreamProxy {
+    pub inner: ConfigBackend,
+}
+
+impl StreamProxy {
+    fn proxy(&self) -> Result<(&[ConfigUpstreamServer], &ConfigBalance, Option<&ConfigProxyOptions>)> {
+        match &self.inner {
+            ConfigBackend::Proxy { target, balance, options } => Ok((target, balance, Some(options))),
+            ConfigBackend::Passthrough { target, balance } => Ok((target, balance, None)),
+            _ => Err(Error::InvalidBackend { key: self.get_key(), message: "stream servers can only proxy".to_owned() }),
+        }
+    }
+}
+
+impl BackendDescriptor for StreamProxy {
+    fn get_key(&self) -> String {
+        self.inner.get_key()
+    }
+
+    fn to_backend_config(&self) -> Result<String> {
+        let (_, _, options) = self.proxy()?;
+        let mut lines = vec![format!("proxy_pass {};", self.get_pass_target()?)];
+        if let Some(options) = options {
+            if let Some(timeout) = &options.connect_timeout {
+                lines.push(format!("proxy_connect_timeout {};", directive_value(self, timeout)?));
+            }
+            // stream has no separate read and send timeouts, only one between
+            // two successive operations in either direction
+            if let Some(timeout) = &options.read_timeout {
+                lines.push(format!("proxy_timeout {};", directive_value(self, timeout)?));
+            }
+        }
+        Ok(lines.join("\n"))
+    }
+
+    fn get_http_blocks(&self) -> Vec<Arc<dyn BackendDescriptor>> {
+        match self.proxy() {
+            Ok((target, balance, _)) => Upstream::of(target, balance).into_iter()
+                .map(|u| Arc::new(u) as Arc<dyn BackendDescriptor>)
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+impl StreamDescriptor for StreamProxy {
+    fn get_pass_target(&self) -> Result<String> {
+        let (target, balance, _) = self.proxy()?;
+        Ok(match Upstream::of(target, balance) {
+            Some(upstream) => upstream.name(),
+            None => directive_value(self, &target[0].server)?.to_owned(),
+        })
+    }
+}
+
+/// Hands TLS connections no passthrough claimed to the HTTPS servers of
+/// `listen`, which listen on `passthrough_socket(listen)` instead.
+#[derive(Debug)]
+pub struct HttpsLoopback {
+    pub listen: String,
+}
+
+impl BackendDescriptor for HttpsLoopback {
+    fn get_key(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    fn to_backend_config(&self) -> Result<String> {
+        Ok(format!("proxy_pass {};", self.get_pass_target()?))
+    }
+}
+
+impl StreamDescriptor for HttpsLoopback {
+    fn get_pass_target(&self) -> Result<String> {
+        Ok(passthrough_socket(&self.listen))
+    }
+
+    // the HTTPS servers would see every client as "unix:" otherwise
+    fn takes_proxy_protocol(&self) -> bool {
+        true
+    }
+}
+
+/// Sends plain HTTP requests to the same URL over HTTPS.
+#[derive(Debug)]
+pub struct HttpsRedirect {
+    pub port: u16,
+}
+
+impl BackendDescriptor for HttpsRedirect {
+    fn get_key(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    fn to_backend_config(&self) -> Result<String> {
+        Ok(match self.port {
+            443 => "return 301 https://$host$request_uri;".to_owned(),
+            port => format!("return 301 https://$host:{}$request_uri;", port),
+        })
+    }
+}
+
+/// Adds a `Strict-Transport-Security` header in front of another descriptor.
+#[derive(Debug)]
+pub struct Hsts {
+    pub max_age: u64,
+    pub include_sub_domains: bool,
+    pub preload: bool,
+    pub inner: Arc<dyn BackendDescriptor>,
+}
+
+impl Hsts {
+    pub fn header_value(&self) -> String {
+        let mut value = format!("max-age={}", self.max_age);
+        if self.include_sub_domains {
+            value += "; includeSubDomains";
+        }
+        if self.preload {
+            value += "; preload";
+        }
+        value
+    }
+}
+
+impl BackendDescriptor for Hsts {
+    fn get_key(&self) -> String {
+        format!("Hsts {{ {}, {} }}", self.header_value(), self.inner.get_key())
+    }
+
+    fn to_backend_config(&self) -> Result<String> {
+        Ok(format!("add_header Strict-Transport-Security \"{}\" always;\n{}",
+            self.header_value(), self.inner.to_backend_config()?))
+    }
+
+    fn get_http_blocks(&self) -> Vec<Arc<dyn BackendDescriptor>> {
+        self.inner.get_http_blocks()
+    }
+
+    fn get_server_blocks(&self) -> Vec<Arc<dyn BackendDescriptor>> {
+        self.inner.get_server_blocks()
+    }
+
+    fn requires_http2(&self) -> bool {
+        self.inner.requires_http2()
+    }
+
+    fn as_passthrough(&self) -> Option<Arc<dyn StreamDescriptor>> {
+        self.inner.as_passthrough()
+    }
+}
+
+pub const ACME_CHALLENGE_LOCATION: &str = "/.well-known/acme-challenge/";
+
+/// Serves the HTTP-01 tokens written by the ACME client.
+#[derive(Debug)]
+pub struct AcmeChallenge {
+    pub directory: PathBuf,
+}
+
+impl BackendDescriptor for AcmeChallenge {
+    fn get_key(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    fn to_backend_config(&self) -> Result<String> {
+        // alias needs the trailing slash to map the rest of the URI to a file name
+        let directory = self.directory.to_string_lossy();
+        if directory.contains(|c: char| c.is_whitespace() || c == ';' || c == '{' || c == '}') {
+            return Err(Error::InvalidBackend {
+                key: self.get_key(),
+                message: format!("{:?} cannot be used as a directive value", directory),
+            });
+        }
+        Ok(format!("default_type text/plain;\nalias {}/;", directory.trim_end_matches('/')))
+    }
+}
+
+/// The `server_name` of catch-all servers, which matches no request.
+pub const CATCH_ALL_HOST: &str = "_";
+
+/// Answers requests for hosts no server takes.
+#[derive(Debug)]
+pub struct CatchAll {
+    pub action: ConfigCatchAllAction,
+}
+
+impl BackendDescriptor for CatchAll {
+    fn get_key(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    fn to_backend_config(&self) -> Result<String> {
+        Ok(match &self.action {
+            // nginx closes the connection without a response
+            ConfigCatchAllAction::Close => "return 444;".to_owned(),
+            ConfigCatchAllAction::Page { status, body } if body.is_empty() => format!("return {};", status),
+            ConfigCatchAllAction::Page { status, body } => format!("default_type text/html;\nreturn {} {};", status, quoted_value(self, body)?),
+            ConfigCatchAllAction::Redirect { target } => format!("return 301 $scheme://{}$request_uri;", directive_value(self, target)?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxy(yaml: &str) -> ConfigBackend {
+        serde_yaml::from_str(&format!("type: proxy\n{}", yaml)).unwrap()
+    }
+
+    #[test]
+    fn backend_config_proxy() {
+        let b = proxy("target: 127.0.0.1:8080");
+        assert_eq!(b.to_backend_config().unwrap(), "proxy_pass http://127.0.0.1:8080;");
+        assert!(b.get_http_blocks().is_empty());
+        let b = proxy("target: https://example.com/api/");
+        assert_eq!(b.to_backend_config().unwrap(), "proxy_pass https://example.com/api/;");
+        let b = proxy("target: unix:/run/app.sock");
+        assert_eq!(b.to_backend_config().unwrap(), "proxy_pass http://unix:/run/app.sock;");
+    }
+
+    #[test]
+    fn backend_config_upstream() {
+        let b = proxy("
+target:
+  - 10.0.0.1:8080
+  - {server: 10.0.0.2:8080, weight: 2, maxFails: 3, failTimeout: 10s}
+  - {server: 10.0.0.3:8080, backup: true, down: true}
+balance: leastConn
+");
+        let blocks = b.get_http_blocks();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].get_key(), "upstream leastConn [10.0.0.1:8080, 10.0.0.2:8080 weight=2 max_fails=3 fail_timeout=10s, 10.0.0.3:8080 backup down]");
+        let name = format!("awsl_{:016x}", fnv1a(&blocks[0].get_key()));
+        assert_eq!(b.to_backend_config().unwrap(), format!("proxy_pass http://{};", name));
+        assert_eq!(blocks[0].to_backend_config().unwrap(), format!("\
+upstream {} {{
+    least_conn;
+    server 10.0.0.1:8080;
+    server 10.0.0.2:8080 weight=2 max_fails=3 fail_timeout=10s;
+    server 10.0.0.3:8080 backup down;
+}}", name));
+
+        // a single address with options still needs an upstream
+        let b = proxy("target: {server: 10.0.0.1:8080, weight: 1}\nbalance: {hash: {key: $request_uri, consistent: true}}");
+        assert!(b.get_http_blocks()[0].to_backend_config().unwrap().contains("    hash $request_uri consistent;\n    server 10.0.0.1:8080 weight=1;"));
+
+        let a = proxy("target: [10.0.0.1:8080, 10.0.0.2:8080]");
+        let c = proxy("target: [10.0.0.1:8080, 10.0.0.2:8080]\nbalance: ipHash");
+        assert_eq!(a.get_http_blocks()[0].get_key(), a.clone().get_http_blocks()[0].get_key());
+        assert_ne!(a.to_backend_config().unwrap(), c.to_backend_config().unwrap());
+        assert_eq!(fnv1a(""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a("a"), 0xaf63dc4c8601ec8c);
+    }
+
+    #[test]
+    fn backend_config_proxy_options() {
+        let b = proxy("
+target: 127.0.0.1:8080
+forwarded: [for, proto, host, realIp]
+requestHeaders: {Host: $host}
+responseHeaders: {X-Frame-Options: DENY}
+connectTimeout: 5s
+readTimeout: 1m
+buffering: false
+maxBodySize: 10m
+websocket: true
+");
+        assert_eq!(b.to_backend_config().unwrap(), "\
+proxy_pass http://127.0.0.1:8080;
+proxy_http_version 1.1;
+proxy_set_header Upgrade $http_upgrade;
+proxy_set_header Connection $connection_upgrade;
+proxy_set_header X-Forwarded-For $proxy_add_x_forwarded_for;
+proxy_set_header X-Forwarded-Proto $scheme;
+proxy_set_header X-Forwarded-Host $host;
+proxy_set_header X-Real-IP $remote_addr;
+proxy_set_header Host \"$host\";
+proxy_connect_timeout 5s;
+proxy_read_timeout 1m;
+proxy_buffering off;
+client_max_body_size 10m;
+add_header X-Frame-Options \"DENY\" always;");
+        let blocks = b.get_http_blocks();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].get_key(), WebsocketUpgrade.get_key());
+        assert_ne!(b.get_key(), proxy("target: 127.0.0.1:8080").get_key());
+
+        let b = proxy("target: 127.0.0.1:8080\nresponseHeaders: {X-Test: 'a\"b'}");
+        assert!(matches!(b.to_backend_config(), Err(Error::InvalidBackend { .. })));
+    }
+
+    #[test]
+    fn backend_config_uwsgi_and_scgi() {
+        let b: ConfigBackend = serde_yaml::from_str("{type: uwsgi, target: 'unix:/run/app.sock', params: {UWSGI_SCHEME: https}, modifier1: 30}").unwrap();
+        assert_eq!(b.to_backend_config().unwrap(), "\
+uwsgi_pass unix:/run/app.sock;
+include uwsgi_params;
+uwsgi_param UWSGI_SCHEME \"https\";
+uwsgi_modifier1 30;");
+        let b: ConfigBackend = serde_yaml::from_str("{type: scgi, target: '127.0.0.1:4000'}").unwrap();
+        assert_eq!(b.to_backend_config().unwrap(), "scgi_pass 127.0.0.1:4000;\ninclude scgi_params;");
+        assert!(!b.requires_http2());
+    }
+
+    #[test]
+    fn backend_config_grpc() {
+        let b: ConfigBackend = serde_yaml::from_str("{type: grpc, target: '127.0.0.1:50051'}").unwrap();
+        assert_eq!(b.to_backend_config().unwrap(), "grpc_pass 127.0.0.1:50051;");
+        assert!(b.requires_http2());
+        assert!(b.get_server_blocks().is_empty());
+
+        let b: ConfigBackend = serde_yaml::from_str("{type: grpc, target: 'grpcs://rpc.internal:443', errors: {502: 14, 503: 14, 504: 4}}").unwrap();
+        assert_eq!(b.to_backend_config().unwrap(), "\
+grpc_pass grpcs://rpc.internal:443;
+grpc_ssl_server_name on;
+error_page 504 = @awsl_grpc_4;
+error_page 502 503 = @awsl_grpc_14;");
+        let blocks = b.get_server_blocks();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[1].to_backend_config().unwrap(), "\
+location @awsl_grpc_14 {
+    default_type application/grpc;
+    add_header grpc-status 14;
+    add_header content-length 0;
+    return 204;
+}");
+        let hsts = Hsts { max_age: 1, include_sub_domains: false, preload: false, inner: Arc::new(b) };
+        assert!(hsts.requires_http2());
+        assert_eq!(hsts.get_server_blocks().len(), 2);
+    }
+
+    #[test]
+    fn backend_config_file_options() {
+        let b: ConfigBackend = serde_yaml::from_str("
+type: file
+path: /srv/app/
+alias: true
+index: [index.html, index.htm]
+autoindex: true
+autoindexFormat: json
+fallback: /app/index.html
+expires: {css: 30d, js: 30d, html: epoch}
+precompressed: [gzip, brotli]
+").unwrap();
+        assert_eq!(b.to_backend_config().unwrap(), "\
+alias /srv/app/;
+index index.html index.htm;
+autoindex on;
+autoindex_format json;
+try_files $uri $uri/ /app/index.html;
+gzip_static on;
+brotli_static on;
+location ~* \\.(css|js)$ {
+    expires 30d;
+}
+location ~* \\.(html)$ {
+    expires epoch;
+}");
+        assert_eq!(b.get_key(), r#"{"type":"file","path":"/srv/app/","alias":true,"index":["index.html","index.htm"],"autoindex":true,"autoindexFormat":"json","fallback":"/app/index.html","expires":{"css":"30d","html":"epoch","js":"30d"},"precompressed":["gzip","brotli"]}"#);
+
+        let b: ConfigBackend = serde_yaml::from_str("{type: file, path: /srv/www, index: index.html, tryFiles: [$uri, $uri.html, =404]}").unwrap();
+        assert_eq!(b.to_backend_config().unwrap(), "root /srv/www;\nindex index.html;\ntry_files $uri $uri.html =404;");
+        assert_eq!(b.get_key(), "/srv/www".parse::<ConfigBackend>().unwrap().get_key().replace('}', r#","index":["index.html"],"tryFiles":["$uri","$uri.html","=404"]}"#));
+    }
+
+    fn rewrite(yaml: &str) -> String {
+        let b: ConfigBackend = serde_yaml::from_str(&yaml.replacen('{', "{type: rewrite, ", 1)).unwrap();
+        b.to_backend_config().unwrap()
+    }
+
+    #[test]
+    fn backend_config_rewrite_options() {
+        assert_eq!(rewrite("{target: 'https://new.example.com/', code: permanent, preservePath: true, preserveQuery: true}"),
+            "return 301 https://new.example.com$request_uri;");
+        assert_eq!(rewrite("{target: 'https://new.example.com', keepMethod: true, preservePath: true}"), "\
+if ($request_uri ~ \"^([^?]*)\") {
+    return 307 https://new.example.com$1;
+}");
+        assert_eq!(rewrite("{target: /landing, code: 303, preserveQuery: true}"), "return 303 /landing$is_args$args;");
+        assert_eq!(rewrite("{target: /posts/$1, pattern: '^/blog/(\\d+)$', code: permanent, keepMethod: true}"), "\
+if ($request_uri ~ \"^/blog/(\\d+)$\") {
+    return 308 /posts/$1;
+}
+return 404;");
+        assert_eq!(rewrite("{target: /index.php, internal: true, preserveQuery: true}"), "rewrite ^ /index.php last;");
+        assert_eq!(rewrite("{target: /app, internal: true, preservePath: true}"), "rewrite ^ /app$uri? last;");
+        assert_eq!(rewrite("{target: /posts/$1, pattern: '^/blog/(\\d+)$', internal: true}"),
+            "rewrite \"^/blog/(\\d+)$\" /posts/$1? last;\nreturn 404;");
+
+        let b: ConfigBackend = serde_yaml::from_str("{type: rewrite, target: /x, pattern: 'a\\\\b'}").unwrap();
+        assert!(matches!(b.to_backend_config(), Err(Error::InvalidBackend { .. })));
+        // the path capture would take the place of the pattern
+        let b: ConfigBackend = serde_yaml::from_str("{type: rewrite, target: /x, pattern: '^/a/', preservePath: true}").unwrap();
+        assert!(matches!(b.to_backend_config(), Err(Error::InvalidBackend { .. })));
+        assert!(serde_yaml::from_str::<ConfigBackend>("{type: rewrite, target: /x, code: forever}").is_err());
+    }
+
+    #[test]
+    fn backend_config_rewrite_keeps_path_encoded() {
+        // nginx decodes $uri, so a request for this would put CRLF into Location through it
+        let request_uri = "/a%0d%0aSet-Cookie:%20x=1?q=1";
+        for yaml in &["{target: 'https://new.example.com', preservePath: true}", "{target: 'https://new.example.com/$1', pattern: '^/(.*)$'}"] {
+            let out = rewrite(yaml);
+            assert!(!out.contains("$uri"), "{}", out);
+            assert!(out.starts_with("if ($request_uri ~ "), "{}", out);
+        }
+        // what nginx captures from the raw $request_uri with ^([^?]*)
+        let out = rewrite("{target: 'https://new.example.com', preservePath: true}");
+        let path = request_uri.split('?').next().unwrap();
+        let location = out.lines().find_map(|l| l.trim().strip_prefix("return 302 ")).unwrap().trim_end_matches(';').replace("$1", path);
+        assert_eq!(location, "https://new.example.com/a%0d%0aSet-Cookie:%20x=1");
+    }
+
+    #[test]
+    fn backend_config_rewrite_and_file() {
+        let b = ConfigBackend::Rewrite { target: "https://example.com".to_owned(), code: 301, options: Default::default() };
+        assert_eq!(b.to_backend_config().unwrap(), "return 301 https://example.com;");
+        let b = "/srv/www".parse::<ConfigBackend>().unwrap();
+        assert_eq!(b.to_backend_config().unwrap(), "root /srv/www;");
+    }
+
+    #[test]
+    fn backend_config_rejects_unsafe_values() {
+        let b = ConfigBackend::Rewrite { target: "/x; return 200".to_owned(), code: 302, options: Default::default() };
+        assert!(matches!(b.to_backend_config(), Err(Error::InvalidBackend { .. })));
+        let b = "/srv/my site".parse::<ConfigBackend>().unwrap();
+        assert!(matches!(b.to_backend_config(), Err(Error::InvalidBackend { .. })));
+    }
+
+    fn fastcgi(yaml: &str) -> ConfigBackend {
+        serde_yaml::from_str(&format!("type: fastcgi\n{}", yaml)).unwrap()
+    }
+
+    #[test]
+    fn backend_config_fastcgi() {
+        let b = fastcgi("target: 127.0.0.1:9000\nroot: /srv/app\nfrontController: /index.php");
+        assert_eq!(b.to_backend_config().unwrap(), "\
+root /srv/app;
+index index.php;
+try_files $uri $uri/ /index.php$is_args$args;
+location ~ \\.php$ {
+    try_files $fastcgi_script_name =404;
+    fastcgi_pass 127.0.0.1:9000;
+    include fastcgi_params;
+    fastcgi_param SCRIPT_FILENAME $document_root$fastcgi_script_name;
+}");
+
+        let b = fastcgi("
+target: unix:/run/php-fpm.sock
+root: /srv/app
+index: app.php
+params: {SCRIPT_FILENAME: /srv/app/app.php, APP_ENV: prod}
+");
+        assert_eq!(b.to_backend_config().unwrap(), "\
+root /srv/app;
+index app.php;
+location ~ \\.php$ {
+    try_files $fastcgi_script_name =404;
+    fastcgi_pass unix:/run/php-fpm.sock;
+    include fastcgi_params;
+    fastcgi_param APP_ENV \"prod\";
+    fastcgi_param SCRIPT_FILENAME \"/srv/app/app.php\";
+}");
+        assert!(b.get_http_blocks().is_empty());
+    }
+
+    #[test]
+    fn backend_config_https_redirect() {
+        assert_eq!(HttpsRedirect { port: 443 }.to_backend_config().unwrap(), "return 301 https://$host$request_uri;");
+        assert_eq!(HttpsRedirect { port: 8443 }.to_backend_config().unwrap(), "return 301 https://$host:8443$request_uri;");
+    }
+
+    #[test]
+    fn backend_config_hsts() {
+        let inner: Arc<dyn BackendDescriptor> = Arc::new("/srv".parse::<ConfigBackend>().unwrap());
+        let b = Hsts { max_age: 600, include_sub_domains: false, preload: false, inner: inner.clone() };
+        assert_eq!(b.to_backend_config().unwrap(), "add_header Strict-Transport-Security \"max-age=600\" always;\nroot /srv;");
+        let c = Hsts { max_age: 600, include_sub_domains: true, preload: true, inner };
+        assert_eq!(c.header_value(), "max-age=600; includeSubDomains; preload");
+        assert_ne!(b.get_key(), c.get_key());
+    }
+
+    #[test]
+    fn backend_config_acme_challenge() {
+        let b = AcmeChallenge { directory: PathBuf::from("/var/lib/awsl/acme/challenges/") };
+        assert_eq!(b.to_backend_config().unwrap(), "default_type text/plain;\nalias /var/lib/awsl/acme/challenges/;");
+    }
+
+    #[test]
+    fn backend_key_differs_by_content() {
+        let a = ConfigBackend::Rewrite { target: "/a".to_owned(), code: 301, options: Default::default() };
+        let b = ConfigBackend::Rewrite { target: "/a".to_owned(), code: 302, options: Default::default() };
+        assert_ne!(a.get_key(), b.get_key());
+        assert_eq!(a.get_key(), a.clone().get_key());
+    }
+}
@@ -0,0 +1,267 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use x509_parser::extensions::GeneralName;
+use x509_parser::pem::parse_x509_pem;
+use super::backend::CATCH_ALL_HOST;
+use super::interface::{Registry, ServerInterfaceAttribute, WebRegistry, WebServer};
+
+const DAY: i64 = 24 * 60 * 60;
+
+/// What `check_certificates` needs from a parsed certificate.
+#[derive(Debug, Clone)]
+pub struct CertificateInfo {
+    /// subjectAltName DNS entries, or the subject CN when there are none.
+    pub names: Vec<String>,
+    /// notAfter as a unix timestamp.
+    pub not_after: i64,
+    /// notAfter as `YYYY-MM-DD`.
+    pub not_after_date: String,
+}
+
+pub fn read_certificate(path: &Path) -> Result<CertificateInfo, String> {
+    let data = fs::read(path).map_err(|e| e.to_string())?;
+    let (_, pem) = parse_x509_pem(&data).map_err(|e| format!("not a PEM certificate: {}", e))?;
+    let cert = pem.parse_x509().map_err(|e| format!("not an X.509 certificate: {}", e))?;
+
+    let san = cert.subject_alternative_name().map_err(|e| e.to_string())?;
+    let names: Vec<String> = match san {
+        Some(san) => san.value.general_names.iter()
+            .filter_map(|n| match n {
+                GeneralName::DNSName(name) => Some(name.to_ascii_lowercase()),
+                _ => None,
+            })
+            .collect(),
+        None => cert.subject().iter_common_name()
+            .filter_map(|cn| cn.as_str().ok())
+            .map(|cn| cn.to_ascii_lowercase())
+            .collect(),
+    };
+    let not_after = cert.validity().not_after;
+    Ok(CertificateInfo {
+        names,
+        not_after: not_after.timestamp(),
+        not_after_date: not_after.to_datetime().date().to_string(),
+    })
+}
+
+/// Reads the DNS names a PEM certificate is valid for.
+pub fn certificate_names(path: &Path) -> Result<Vec<String>, String> {
+    read_certificate(path).map(|c| c.names)
+}
+
+/// Whether certificate name `name` matches `host`, where a leading `*.`
+/// stands for exactly one label.
+fn name_matches(name: &str, host: &str) -> bool {
+    match name.strip_prefix("*.") {
+        Some(suffix) => match host.split_once('.') {
+            Some((label, rest)) => !label.is_empty() && label != "*" && rest == suffix,
+            None => false,
+        },
+        None => name == host,
+    }
+}
+
+/// Whether `names` cover every name the nginx `server_name` entry `host`
+/// can match. Trailing wildcards such as `example.*` cannot be checked
+/// against a certificate and are always accepted.
+pub fn names_cover(names: &[String], host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    let covers = |h: &str| names.iter().any(|n| n == h || name_matches(n, h));
+    if host.ends_with(".*") {
+        true
+    } else if host.starts_with("*.") {
+        // only a wildcard certificate name covers a wildcard host
+        names.contains(&host)
+    } else if let Some(rest) = host.strip_prefix('.') {
+        covers(rest) && names.contains(&format!("*.{}", rest))
+    } else {
+        covers(&host)
+    }
+}
+
+/// Where `check_certificates` looks for certificates and what it flags.
+#[derive(Debug, Clone)]
+pub struct CheckOptions {
+    /// Holds `<host>.pem` or `<host>/fullchain.pem` for hosts whose
+    /// interfaces carry no TLS settings.
+    pub directory: Option<PathBuf>,
+    /// Certificates expiring within this many days are flagged.
+    pub window_days: u32,
+    /// Current unix time, so checks can be reproduced.
+    pub now: i64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Finding {
+    /// Neither TLS settings nor the certificate directory name a file.
+    NotConfigured,
+    Unreadable(String),
+    Expired,
+    ExpiresSoon { days: i64 },
+    /// The host is not matched by any SAN of the certificate.
+    NotCovered,
+}
+
+/// The outcome of inspecting the certificate served for one host.
+#[derive(Debug, Clone)]
+pub struct CertificateCheck {
+    pub host: String,
+    pub ports: Vec<u16>,
+    pub path: Option<PathBuf>,
+    pub info: Option<CertificateInfo>,
+    pub findings: Vec<Finding>,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Finding::NotConfigured => write!(f, "no certificate configured"),
+            Finding::Unreadable(message) => write!(f, "cannot read certificate: {}", message),
+            Finding::Expired => write!(f, "certificate has expired"),
+            Finding::ExpiresSoon { days } => write!(f, "certificate expires in {} day(s)", days),
+            Finding::NotCovered => write!(f, "host is not covered by the certificate"),
+        }
+    }
+}
+
+impl fmt::Display for CertificateCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:?}", self.host, self.ports)?;
+        if let Some(path) = &self.path {
+            write!(f, ": {}", path.display())?;
+        }
+        if let Some(info) = &self.info {
+            write!(f, ", expires {}", info.not_after_date)?;
+        }
+        Ok(())
+    }
+}
+
+fn locate(directory: &Path, host: &str) -> PathBuf {
+    let flat = directory.join(format!("{}.pem", host));
+    let nested = directory.join(host).join("fullchain.pem");
+    if !flat.exists() && nested.exists() {
+        nested
+    } else {
+        flat
+    }
+}
+
+fn inspect(host: &str, path: &Path, options: &CheckOptions) -> (Option<CertificateInfo>, Vec<Finding>) {
+    let info = match read_certificate(path) {
+        Ok(info) => info,
+        Err(message) => return (None, vec![Finding::Unreadable(message)]),
+    };
+    let mut findings = Vec::new();
+    let left = info.not_after - options.now;
+    if left <= 0 {
+        findings.push(Finding::Expired);
+    } else if left < i64::from(options.window_days) * DAY {
+        findings.push(Finding::ExpiresSoon { days: left / DAY });
+    }
+    if !names_cover(&info.names, host) {
+        findings.push(Finding::NotCovered);
+    }
+    (Some(info), findings)
+}
+
+/// Inspects the certificate of every host served on an HTTPS interface of
+/// `reg`, once per host and certificate file.
+pub fn check_certificates(reg: &Registry, options: &CheckOptions) -> Vec<CertificateCheck> {
+    let mut checks: Vec<CertificateCheck> = Vec::new();
+    // passed-through hosts present the certificates of their targets, and
+    // catch-all servers a fallback that no host name matches
+    let checked = |w: &&WebServer| w.get_passthrough().is_none() && !w.get_host().iter().any(|h| h == CATCH_ALL_HOST);
+    for web in reg.get_web_servers().iter().filter(checked) {
+        for interface in web.get_interface() {
+            if interface.get_attr() != ServerInterfaceAttribute::Https {
+                continue;
+            }
+            for host in web.get_host() {
+                let path = match (interface.get_tls().and_then(|t| t.get_certificate()), &options.directory) {
+                    (Some(path), _) => Some(path.to_owned()),
+                    (None, Some(directory)) => Some(locate(directory, host)),
+                    (None, None) => None,
+                };
+                if let Some(check) = checks.iter_mut().find(|c| c.host == *host && c.path == path) {
+                    if !check.ports.contains(&interface.get_port()) {
+                        check.ports.push(interface.get_port());
+                    }
+                    continue;
+                }
+                let (info, findings) = match &path {
+                    Some(path) => inspect(host, path, options),
+                    None => (None, vec![Finding::NotConfigured]),
+                };
+                checks.push(CertificateCheck { host: host.clone(), ports: vec![interface.get_port()], path, info, findings });
+            }
+        }
+    }
+    checks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use super::super::config::ConfigTls;
+    use super::super::interface::*;
+    use super::super::test_util::instance;
+
+    #[test]
+    fn cert_reads_san() {
+        let names = certificate_names(Path::new("tests/fixtures/tls/example.com.pem")).unwrap();
+        assert_eq!(names, vec!["example.com".to_owned(), "*.example.com".to_owned()]);
+        assert!(certificate_names(Path::new("tests/fixtures/tls/example.com.key")).is_err());
+        assert!(certificate_names(Path::new("tests/fixtures/tls/missing.pem")).is_err());
+    }
+
+    #[test]
+    fn cert_names_cover_hosts() {
+        let names = vec!["example.com".to_owned(), "*.example.com".to_owned()];
+        assert!(names_cover(&names, "example.com"));
+        assert!(names_cover(&names, "WWW.example.com"));
+        assert!(!names_cover(&names, "a.b.example.com"));
+        assert!(!names_cover(&names, "example.org"));
+        assert!(names_cover(&names, "*.example.com"));
+        assert!(names_cover(&names, ".example.com"));
+        assert!(names_cover(&names, "www.example.*"));
+
+        let names = vec!["example.com".to_owned()];
+        assert!(!names_cover(&names, "www.example.com"));
+        assert!(!names_cover(&names, ".example.com"));
+        assert!(!names_cover(&names, "*.example.com"));
+    }
+
+    #[test]
+    fn cert_check_registry() {
+        let tls: ConfigTls = serde_yaml::from_str("{certificate: tests/fixtures/tls/example.com.pem, key: tests/fixtures/tls/example.com.key}").unwrap();
+        let tls: Arc<dyn TlsDescriptor> = Arc::new(tls);
+        let https = |port| ServerInterface::new(port, ServerInterfaceAttribute::Https);
+        let inst = |host, interface| instance(host, interface, None, "null");
+
+        let mut reg: Registry = Default::default();
+        reg.add_server(&inst(&["example.com", "example.net"], vec![https(443).with_tls(tls.clone()), https(8443).with_tls(tls)]), OverwritePolicy::Error).unwrap();
+        reg.add_server(&inst(&["example.org", "missing.example.org"], vec![https(443)]), OverwritePolicy::Error).unwrap();
+        reg.add_server(&inst(&["example.org"], vec![ServerInterface::new(80, ServerInterfaceAttribute::Http)]), OverwritePolicy::Error).unwrap();
+
+        let not_after = read_certificate(Path::new("tests/fixtures/tls/example.com.pem")).unwrap().not_after;
+        let mut options = CheckOptions { directory: Some(PathBuf::from("tests/fixtures/tls")), window_days: 30, now: not_after - 10 * DAY };
+        let checks = check_certificates(&reg, &options);
+        let summary: Vec<(&str, &[u16], &[Finding])> = checks.iter().map(|c| (c.host.as_str(), &c.ports[..], &c.findings[..])).collect();
+        assert_eq!(summary[0], ("example.com", &[443, 8443][..], &[Finding::ExpiresSoon { days: 10 }][..]));
+        assert_eq!(summary[1], ("example.net", &[443, 8443][..], &[Finding::ExpiresSoon { days: 10 }, Finding::NotCovered][..]));
+        assert_eq!(summary[2].0, "example.org");
+        assert!(matches!(summary[3], ("missing.example.org", _, [Finding::Unreadable(_)])));
+        assert_eq!(checks.len(), 4);
+        assert_eq!(checks[0].to_string(), "example.com [443, 8443]: tests/fixtures/tls/example.com.pem, expires 2126-09-22");
+
+        options.window_days = 5;
+        assert!(check_certificates(&reg, &options)[0].findings.is_empty());
+        options.now = not_after;
+        assert_eq!(check_certificates(&reg, &options)[0].findings, vec![Finding::Expired]);
+        options.directory = None;
+        assert_eq!(check_certificates(&reg, &options)[3].findings, vec![Finding::NotConfigured]);
+    }
+}
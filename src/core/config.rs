@@ -1,10 +1,13 @@
 use std::collections::BTreeMap as Map;
 use serde::{Serialize, Deserialize, Deserializer};
 use std::fmt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::marker::PhantomData;
+use std::convert::TryFrom;
 use std::str::FromStr;
-use serde::de::{self, Visitor, MapAccess, SeqAccess};
+use serde::de::{self, Visitor, MapAccess, SeqAccess, IntoDeserializer};
+use super::cert::{certificate_names, names_cover};
+use super::error::Error;
 type Void = std::convert::Infallible;
 
 /*
@@ -19,6 +22,7 @@ templates:
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
+#[allow(clippy::upper_case_acronyms)]
 pub enum ConfigHttpHttps {
     Only,
     #[serde(rename = "hsts", rename_all = "camelCase")]
@@ -36,24 +40,377 @@ pub enum ConfigHttpHttps {
 	Disabled,
 }
 
-fn http_default_port() -> u16 { 80 }
-fn https_default_port() -> u16 { 80 }
+/*
+    port:
+      http: [80, '10.0.0.1:8080', '[::]:80', 'unix:/run/awsl/http.sock']
+*/
+
+/// One entry of a port list: a port on every address, `address:port`
+/// with an IPv4 or a bracketed IPv6 address, or `unix:/path`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigListen {
+	pub address: Option<String>,
+	/// 0 for unix sockets.
+	pub port: u16,
+}
+
+impl ConfigListen {
+    pub fn is_unix(&self) -> bool {
+        self.address.as_deref().is_some_and(|a| a.starts_with("unix:"))
+    }
+}
+
+impl From<u16> for ConfigListen {
+    fn from(port: u16) -> Self {
+        ConfigListen { address: None, port }
+    }
+}
+
+fn parse_port(port: &str) -> Result<u16, String> {
+    match port.parse::<u16>() {
+        Ok(p) if p != 0 => Ok(p),
+        _ => Err(format!("{:?} is not a port", port)),
+    }
+}
+
+impl FromStr for ConfigListen {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if !path.starts_with('/') {
+                return Err(format!("{:?} is not an absolute path", path));
+            }
+            return Ok(ConfigListen { address: Some(s.to_owned()), port: 0 });
+        }
+        let (address, port) = match s.rsplit_once(':') {
+            Some((address, port)) => (address, port),
+            None => return Ok(parse_port(s)?.into()),
+        };
+        let valid = match address.strip_prefix('[').and_then(|a| a.strip_suffix(']')) {
+            Some(v6) => v6.parse::<std::net::Ipv6Addr>().is_ok(),
+            None => address == "*" || address.parse::<std::net::Ipv4Addr>().is_ok(),
+        };
+        if !valid {
+            return Err(format!("{:?} is neither an IPv4 address nor an IPv6 address in brackets", address));
+        }
+        Ok(ConfigListen { address: Some(address.to_owned()), port: parse_port(port)? })
+    }
+}
+
+impl fmt::Display for ConfigListen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.address {
+            Some(address) if self.is_unix() => f.write_str(address),
+            Some(address) => write!(f, "{}:{}", address, self.port),
+            None => write!(f, "{}", self.port),
+        }
+    }
+}
+
+impl Serialize for ConfigListen {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.address {
+            None => serializer.serialize_u16(self.port),
+            Some(_) => serializer.collect_str(self),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ConfigListen {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ListenVisitor;
+
+        impl<'de> Visitor<'de> for ListenVisitor {
+            type Value = ConfigListen;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a port, address:port or unix:/path")
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<ConfigListen, E> {
+                match u16::try_from(value) {
+                    Ok(port) if port != 0 => Ok(ConfigListen::from(port)),
+                    _ => Err(E::custom(format!("{} is not a port", value))),
+                }
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<ConfigListen, E> {
+                Err(E::custom(format!("{} is not a port", value)))
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<ConfigListen, E> {
+                value.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(ListenVisitor)
+    }
+}
+
+fn http_default_port() -> Vec<ConfigListen> { vec![80.into()] }
+fn https_default_port() -> Vec<ConfigListen> { vec![443.into()] }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ConfigHttpPort {
-	#[serde(default = "http_default_port")]
-	pub http: u16,
-	#[serde(default = "https_default_port")]
-	pub https: u16,
+	#[serde(default = "http_default_port", deserialize_with = "one_or_list")]
+	pub http: Vec<ConfigListen>,
+	#[serde(default = "https_default_port", deserialize_with = "one_or_list")]
+	pub https: Vec<ConfigListen>,
+}
+
+/*
+    listen:
+      http2: true
+      http3: true # quic listener, advertised by Alt-Svc
+      proxyProtocol: [10.0.0.0/8] # load balancers trusted for client addresses
+      defaultServer: true
+      reuseport: true
+*/
+
+/// `listen` parameters of a template's interfaces; HTTP/2 and HTTP/3 apply
+/// to its HTTPS ports only.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ConfigListenOptions {
+	pub http2: bool,
+	pub http3: bool,
+	pub proxy_protocol: Option<Vec<String>>,
+	pub default_server: bool,
+	pub reuseport: bool,
+}
+
+impl Default for ConfigHttpPort {
+	fn default() -> Self {
+		ConfigHttpPort {
+			http: http_default_port(),
+			https: https_default_port(),
+		}
+	}
+}
+
+/*
+    tls:
+      certificate: /etc/ssl/example.com.pem
+      key: /etc/ssl/example.com.key
+      hosts:
+        example.org:
+          certificate: /etc/ssl/example.org.pem
+          key: /etc/ssl/example.org.key
+      protocols: [TLSv1.2, TLSv1.3]
+      ciphers: intermediate # modern
+      ocspStapling: true
+      sessionTickets: false
+*/
+
+pub const TLS_PROTOCOLS: &[&str] = &["TLSv1", "TLSv1.1", "TLSv1.2", "TLSv1.3"];
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ConfigCertificate {
+	pub certificate: PathBuf,
+	pub key: PathBuf,
+}
+
+/// Mozilla's server side TLS presets.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ConfigTlsCiphers {
+	Modern,
+	Intermediate,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigTls {
+	/// May be left out when `acme` issues the certificate.
+	#[serde(default)]
+	pub certificate: PathBuf,
+	#[serde(default)]
+	pub key: PathBuf,
+	#[serde(default, skip_serializing_if = "Map::is_empty")]
+	pub hosts: Map<String, ConfigCertificate>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub protocols: Vec<String>,
+	pub ciphers: Option<ConfigTlsCiphers>,
+	#[serde(default)]
+	pub ocsp_stapling: bool,
+	pub session_tickets: Option<bool>,
+}
+
+impl ConfigTls {
+    /// The settings used for `host`, with its entry in `hosts` applied.
+    pub fn for_host(&self, host: &str) -> ConfigTls {
+        let mut tls = self.clone();
+        if let Some(cert) = self.hosts.get(host) {
+            tls.certificate = cert.certificate.clone();
+            tls.key = cert.key.clone();
+        }
+        tls.hosts.clear();
+        tls
+    }
+}
+
+/*
+    acme:
+      directory: https://acme-v02.api.letsencrypt.org/directory
+      email: admin@example.com
+      state: /var/lib/awsl/acme
+      renewBefore: 30 # days
+*/
+
+fn acme_default_directory() -> String { "https://acme-v02.api.letsencrypt.org/directory".to_owned() }
+fn acme_default_state() -> PathBuf { PathBuf::from("/var/lib/awsl/acme") }
+fn acme_default_renew_before() -> u32 { 30 }
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigAcme {
+	#[serde(default = "acme_default_directory")]
+	pub directory: String,
+	pub email: Option<String>,
+	#[serde(default = "acme_default_state")]
+	pub state: PathBuf,
+	#[serde(default = "acme_default_renew_before")]
+	pub renew_before: u32,
+}
+
+impl ConfigAcme {
+    pub fn certificate_path(&self, host: &str) -> PathBuf {
+        self.state.join(host).join("fullchain.pem")
+    }
+
+    pub fn key_path(&self, host: &str) -> PathBuf {
+        self.state.join(host).join("privkey.pem")
+    }
+
+    /// Whether the certificate of `host` has been issued yet.
+    pub fn is_issued(&self, host: &str) -> bool {
+        self.certificate_path(host).is_file() && self.key_path(host).is_file()
+    }
+
+    /// Where HTTP-01 tokens are written while an order is pending.
+    pub fn challenge_directory(&self) -> PathBuf {
+        self.state.join("challenges")
+    }
+
+    pub fn account_key_path(&self) -> PathBuf {
+        self.state.join("account.pk8")
+    }
+
+    /// `base` (or bare defaults) with the certificate issued for `host`.
+    pub fn tls_for(&self, host: &str, base: Option<ConfigTls>) -> ConfigTls {
+        let certificate = self.certificate_path(host);
+        let key = self.key_path(host);
+        match base {
+            Some(tls) => ConfigTls { certificate, key, ..tls },
+            None => ConfigTls {
+                certificate,
+                key,
+                hosts: Map::new(),
+                protocols: Vec::new(),
+                ciphers: None,
+                ocsp_stapling: false,
+                session_tickets: None,
+            },
+        }
+    }
+}
+
+
+/*
+templates:
+  db:
+    module: stream
+    protocol: tcp # or udp
+    port: 5432
+    tls: # terminate TLS, or
+      certificate: /etc/ssl/db.pem
+      key: /etc/ssl/db.key
+    preread: true # pass TLS through, routed by SNI
+*/
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum ConfigStreamProtocol {
+	#[default]
+	Tcp,
+	Udp,
+}
+
+/*
+catchAll: # answers requests for hosts no server takes
+  type: close # or page, redirect
+  status: 404 # page
+  body: <h1>Not found</h1> # page
+  target: www.example.com # redirect
+  tls: # self-signed when left out; close refuses the TLS handshake instead
+    certificate: /etc/ssl/fallback.pem
+    key: /etc/ssl/fallback.key
+*/
+
+fn page_default_status() -> u16 { 404 }
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum ConfigCatchAllAction {
+	/// Closes the connection without a response.
+	Close,
+	Page {
+		#[serde(default = "page_default_status")]
+		status: u16,
+		#[serde(default, skip_serializing_if = "String::is_empty")]
+		body: String,
+	},
+	/// Redirects to the same path on a canonical host.
+	Redirect {
+		target: String,
+	},
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ConfigCatchAll {
+	#[serde(flatten)]
+	pub action: ConfigCatchAllAction,
+	pub tls: Option<ConfigTls>,
+}
+
+impl ConfigCatchAll {
+    /// The certificate shown on HTTPS interfaces; `None` refuses handshakes.
+    pub fn effective_tls(&self, cfg: &Config) -> Option<ConfigTls> {
+        match (&self.tls, &self.action) {
+            (Some(tls), _) => Some(tls.clone()),
+            (None, ConfigCatchAllAction::Close) => None,
+            (None, _) => Some(cfg.certificates.self_signed()),
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase", tag = "module")]
 pub enum ConfigServerTemplate {
 	Http {
 		https: ConfigHttpHttps,
+		#[serde(default)]
 		port: ConfigHttpPort,
+		tls: Option<ConfigTls>,
+		acme: Option<ConfigAcme>,
+		#[serde(default)]
+		listen: ConfigListenOptions,
+		/// Overrides the config's `catchAll` on this template's interfaces.
+		#[serde(rename = "catchAll")]
+		catch_all: Option<Box<ConfigCatchAll>>,
+	},
+	/// Raw TCP/UDP forwarding, rendered into nginx's `stream {}` block.
+	Stream {
+		#[serde(default)]
+		protocol: ConfigStreamProtocol,
+		#[serde(deserialize_with = "one_or_list")]
+		port: Vec<u16>,
+		/// Terminates TLS in front of the targets.
+		tls: Option<ConfigTls>,
+		/// Routes connections by their SNI name without terminating TLS;
+		/// the servers' hosts are the names they take.
+		#[serde(default)]
+		preread: bool,
 	},
 }
 
@@ -69,21 +426,385 @@ servers:
       target: 127.20.1.1:32
 */
 
+/*
+    backend:
+      type: proxy
+      target:
+        - 10.0.0.1:8080
+        - server: 10.0.0.2:8080
+          weight: 2
+          maxFails: 3
+          failTimeout: 10s
+        - server: 10.0.0.3:8080
+          backup: true
+      balance: leastConn # roundRobin, ipHash, hash: {key: $request_uri, consistent: true}
+*/
+
+/// One `server` line of an upstream; a plain string is just the address.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(rename_all = "camelCase", remote = "Self")]
+pub struct ConfigUpstreamServer {
+	pub server: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub weight: Option<u32>,
+	#[serde(default, skip_serializing_if = "is_false")]
+	pub backup: bool,
+	#[serde(default, skip_serializing_if = "is_false")]
+	pub down: bool,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub max_fails: Option<u32>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub fail_timeout: Option<String>,
+}
+
+fn is_false(value: &bool) -> bool { !*value }
+
+impl ConfigUpstreamServer {
+    /// Whether only the address is set.
+    pub fn is_plain(&self) -> bool {
+        *self == ConfigUpstreamServer { server: self.server.clone(), ..Default::default() }
+    }
+}
+
+impl FromStr for ConfigUpstreamServer {
+	type Err = Void;
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(ConfigUpstreamServer {
+			server: s.to_owned(),
+			..Default::default()
+		})
+	}
+}
+
+impl<'de> Deserialize<'de> for ConfigUpstreamServer {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct StringOrFields;
+
+        impl<'de> Visitor<'de> for StringOrFields {
+            type Value = ConfigUpstreamServer;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("address or map")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<ConfigUpstreamServer, E> {
+                Ok(FromStr::from_str(value).unwrap())
+            }
+
+            fn visit_map<M: MapAccess<'de>>(self, map: M) -> Result<ConfigUpstreamServer, M::Error> {
+                // the derived (remote) impl handles the map form
+                ConfigUpstreamServer::deserialize(de::value::MapAccessDeserializer::new(map))
+            }
+        }
+
+        deserializer.deserialize_any(StringOrFields)
+    }
+}
+
+impl Serialize for ConfigUpstreamServer {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.is_plain() {
+            serializer.serialize_str(&self.server)
+        } else {
+            ConfigUpstreamServer::serialize(self, serializer)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum ConfigBalance {
+	#[default]
+	RoundRobin,
+	LeastConn,
+	IpHash,
+	#[serde(rename_all = "camelCase")]
+	Hash {
+		key: String,
+		#[serde(default)]
+		consistent: bool,
+	},
+}
+
+impl ConfigBalance {
+    pub fn is_round_robin(&self) -> bool {
+        *self == ConfigBalance::RoundRobin
+    }
+}
+
+/*
+    backend:
+      type: proxy
+      target: 127.0.0.1:8080
+      forwarded: [for, proto, host, realIp]
+      requestHeaders: {Host: $host}
+      responseHeaders: {X-Frame-Options: DENY}
+      connectTimeout: 5s
+      readTimeout: 60s
+      sendTimeout: 60s
+      buffering: false
+      maxBodySize: 10m
+      websocket: true
+*/
+
+/// The usual headers telling the target about the original request.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ConfigForwardedHeader {
+	/// `X-Forwarded-For`
+	For,
+	/// `X-Forwarded-Proto`
+	Proto,
+	/// `X-Forwarded-Host`
+	Host,
+	/// `X-Real-IP`
+	RealIp,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigProxyOptions {
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub forwarded: Vec<ConfigForwardedHeader>,
+	/// Sent to the target, replacing what the client sent.
+	#[serde(default, skip_serializing_if = "Map::is_empty")]
+	pub request_headers: Map<String, String>,
+	/// Added to every response, whatever its status.
+	#[serde(default, skip_serializing_if = "Map::is_empty")]
+	pub response_headers: Map<String, String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub connect_timeout: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub read_timeout: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub send_timeout: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub buffering: Option<bool>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub max_body_size: Option<String>,
+	#[serde(default, skip_serializing_if = "is_false")]
+	pub websocket: bool,
+}
+
+/*
+    backend:
+      type: file
+      path: /srv/app/dist
+      alias: false # map the location onto path instead of appending it
+      index: [index.html]
+      autoindex: true
+      autoindexFormat: json
+      fallback: /index.html # or tryFiles: [$uri, $uri.html, =404]
+      expires:
+        css: 30d
+        js: 30d
+        html: epoch
+      precompressed: [gzip, brotli]
+*/
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ConfigAutoindexFormat {
+	Html,
+	Xml,
+	Json,
+	Jsonp,
+}
+
+/// Precompressed siblings (`.gz`, `.br`) served in place of a file.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ConfigCompression {
+	Gzip,
+	Brotli,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigFileOptions {
+	/// `alias` replaces the location prefix with `path`; the default
+	/// `root` appends the whole URI to it.
+	#[serde(default, skip_serializing_if = "is_false")]
+	pub alias: bool,
+	#[serde(default, deserialize_with = "string_or_list", skip_serializing_if = "Vec::is_empty")]
+	pub index: Vec<String>,
+	#[serde(default, skip_serializing_if = "is_false")]
+	pub autoindex: bool,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub autoindex_format: Option<ConfigAutoindexFormat>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub try_files: Vec<String>,
+	/// URI served when no file matches, as single page apps need.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub fallback: Option<String>,
+	/// File extension to `expires` value.
+	#[serde(default, skip_serializing_if = "Map::is_empty")]
+	pub expires: Map<String, String>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub precompressed: Vec<ConfigCompression>,
+}
+
 fn rewrite_default_code() -> u16 { 302 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/*
+    backend:
+      type: rewrite
+      target: https://new.example.com
+      code: permanent # temporary, or a 3xx status
+      keepMethod: true # 301 -> 308, 302 -> 307
+      preservePath: true
+      preserveQuery: true
+
+    backend:
+      type: rewrite
+      pattern: ^/blog/(\d+)$
+      target: /posts/$1
+      internal: true
+*/
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigRewriteOptions {
+	/// Appends the request path to `target`.
+	#[serde(default, skip_serializing_if = "is_false")]
+	pub preserve_path: bool,
+	/// Appends the query string to `target`.
+	#[serde(default, skip_serializing_if = "is_false")]
+	pub preserve_query: bool,
+	/// Only URIs matching this regex are rewritten, others get 404;
+	/// `target` may refer to its captures as `$1`, `$2`... Redirects match
+	/// the raw request URI, query string included, so captures stay encoded.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub pattern: Option<String>,
+	/// Answers 308 for 301 and 307 for 302, so clients keep the method.
+	#[serde(default, skip_serializing_if = "is_false")]
+	pub keep_method: bool,
+	/// Rewrites the URI inside nginx instead of redirecting the client.
+	#[serde(default, skip_serializing_if = "is_false")]
+	pub internal: bool,
+}
+
+/// A status code, or `permanent` (301) / `temporary` (302).
+fn redirect_code<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u16, D::Error> {
+    struct Code;
+
+    impl<'de> Visitor<'de> for Code {
+        type Value = u16;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("status code, permanent or temporary")
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<u16, E> {
+            u16::try_from(value).map_err(|_| E::invalid_value(de::Unexpected::Unsigned(value), &self))
+        }
+
+        fn visit_i64<E: de::Error>(self, value: i64) -> Result<u16, E> {
+            u16::try_from(value).map_err(|_| E::invalid_value(de::Unexpected::Signed(value), &self))
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<u16, E> {
+            match value {
+                "permanent" => Ok(301),
+                "temporary" => Ok(302),
+                _ => Err(E::invalid_value(de::Unexpected::Str(value), &self)),
+            }
+        }
+    }
+
+    deserializer.deserialize_any(Code)
+}
+fn fastcgi_default_index() -> String { "index.php".to_owned() }
+
+/*
+    backend:
+      type: uwsgi # or scgi
+      target: unix:/run/uwsgi/app.sock
+      params: {UWSGI_SCHEME: https}
+      modifier1: 30
+
+    backend:
+      type: grpc
+      target: grpcs://10.0.0.1:50051
+      errors: {502: 14, 503: 14, 504: 4}
+*/
+
+/*
+    backend:
+      type: fastcgi
+      target: unix:/run/php/php-fpm.sock # or 127.0.0.1:9000
+      root: /srv/app/public
+      index: index.php
+      frontController: /index.php
+      params:
+        APP_ENV: production
+*/
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase", tag = "type")]
 pub enum ConfigBackend {
 	Proxy {
-		target: String,
+		#[serde(deserialize_with = "one_or_list")]
+		target: Vec<ConfigUpstreamServer>,
+		#[serde(default, skip_serializing_if = "ConfigBalance::is_round_robin")]
+		balance: ConfigBalance,
+		#[serde(flatten)]
+		options: Box<ConfigProxyOptions>,
 	},
 	Rewrite {
 		target: String,
-		#[serde(default = "rewrite_default_code")]
+		#[serde(default = "rewrite_default_code", deserialize_with = "redirect_code")]
 		code: u16,
+		#[serde(flatten)]
+		options: Box<ConfigRewriteOptions>,
 	},
 	File {
 		path: PathBuf,
+		#[serde(flatten)]
+		options: Box<ConfigFileOptions>,
+	},
+	/// Serves files from `root` and hands `*.php` to a FastCGI server.
+	#[serde(rename = "fastcgi", rename_all = "camelCase")]
+	FastCgi {
+		target: String,
+		root: PathBuf,
+		#[serde(default = "fastcgi_default_index")]
+		index: String,
+		/// Where requests for missing files go, e.g. `/index.php`.
+		front_controller: Option<String>,
+		/// Extra `fastcgi_param`s; `SCRIPT_FILENAME` replaces the default.
+		#[serde(default, skip_serializing_if = "Map::is_empty")]
+		params: Map<String, String>,
+	},
+	Uwsgi {
+		/// `host:port` or `unix:/path`, optionally `uwsgi://` or `suwsgi://`.
+		target: String,
+		#[serde(default, skip_serializing_if = "Map::is_empty")]
+		params: Map<String, String>,
+		modifier1: Option<u8>,
+		modifier2: Option<u8>,
+	},
+	Scgi {
+		target: String,
+		#[serde(default, skip_serializing_if = "Map::is_empty")]
+		params: Map<String, String>,
+	},
+	/// Hands whole TLS connections to `target` by their SNI name, without
+	/// decrypting them; the server's hosts own their HTTPS ports.
+	Passthrough {
+		#[serde(deserialize_with = "one_or_list")]
+		target: Vec<ConfigUpstreamServer>,
+		#[serde(default, skip_serializing_if = "ConfigBalance::is_round_robin")]
+		balance: ConfigBalance,
+	},
+	/// Forces HTTP/2 on the interfaces the server listens on.
+	Grpc {
+		/// `host:port` or `unix:/path`, optionally `grpc://` or `grpcs://`.
+		target: String,
+		/// HTTP status nginx would answer with mapped to the gRPC status
+		/// clients get instead, e.g. `502: 14` (unavailable).
+		#[serde(default, skip_serializing_if = "Map::is_empty")]
+		errors: Map<u16, u8>,
 	},
 }
 
@@ -91,7 +812,8 @@ impl FromStr for ConfigBackend {
 	type Err = Void;
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
 		Ok(ConfigBackend::File {
-			path: PathBuf::from(s)
+			path: PathBuf::from(s),
+			options: Default::default(),
 		})
 	}
 }
@@ -101,18 +823,107 @@ pub struct ConfigServer {
 	pub name: Option<String>,
 
 	pub template: String,
-	#[serde(deserialize_with = "string_or_list")]
+	/// Required by http templates; stream templates take SNI names here
+	/// when they preread, and nothing otherwise.
+	#[serde(default, deserialize_with = "string_or_list", skip_serializing_if = "Vec::is_empty")]
 	pub host: Vec<String>,
 	pub location: Option<String>,
 
 	#[serde(deserialize_with = "string_or_struct")]
 	pub backend: ConfigBackend,
+
+	/// Replaces the TLS settings of the template for this server.
+	pub tls: Option<ConfigTls>,
+}
+
+impl ConfigServer {
+    /// The TLS settings in effect: the server's own, else its template's.
+    pub fn effective_tls<'a>(&'a self, cfg: &'a Config) -> Option<&'a ConfigTls> {
+        self.tls.as_ref().or(match cfg.templates.get(&self.template) {
+            Some(ConfigServerTemplate::Http { tls, .. } | ConfigServerTemplate::Stream { tls, .. }) => tls.as_ref(),
+            None => None,
+        })
+    }
+
+    /// The ACME settings that issue this server's certificates; a server
+    /// with its own `tls` brings its own certificates instead.
+    pub fn acme<'a>(&'a self, cfg: &'a Config) -> Option<&'a ConfigAcme> {
+        match (&self.tls, cfg.templates.get(&self.template)) {
+            (None, Some(ConfigServerTemplate::Http { acme, .. })) => acme.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// The TLS settings served for `host`, if any. An ACME host shows the
+    /// self-signed certificate until its own is issued, so nginx loads and
+    /// can answer the challenge.
+    pub fn host_tls(&self, cfg: &Config, host: &str) -> Option<ConfigTls> {
+        let tls = self.effective_tls(cfg).map(|t| t.for_host(host));
+        match self.acme(cfg) {
+            Some(acme) if acme.is_issued(host) => Some(acme.tls_for(host, tls)),
+            Some(acme) => {
+                let fallback = cfg.certificates.self_signed();
+                Some(ConfigTls { certificate: fallback.certificate, key: fallback.key, ..acme.tls_for(host, tls) })
+            },
+            None => tls,
+        }
+    }
+
+    /// Whether some ACME host of this server still waits for its certificate.
+    pub fn awaits_issuance(&self, cfg: &Config) -> bool {
+        self.acme(cfg).is_some_and(|acme| self.host.iter().any(|h| !acme.is_issued(h)))
+    }
 }
 
 
 
 
 
+/*
+certificates:
+  directory: /etc/awsl/certs # <host>.pem or <host>/fullchain.pem
+  expiryWindow: 30 # days
+  fallback: /var/lib/awsl/fallback # self-signed certificate of catch-all servers
+*/
+
+fn expiry_window_default() -> u32 { 30 }
+fn fallback_default() -> PathBuf { PathBuf::from("/var/lib/awsl/fallback") }
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigCertificates {
+	pub directory: Option<PathBuf>,
+	#[serde(default = "expiry_window_default")]
+	pub expiry_window: u32,
+	#[serde(default = "fallback_default")]
+	pub fallback: PathBuf,
+}
+
+impl Default for ConfigCertificates {
+	fn default() -> Self {
+		ConfigCertificates {
+			directory: None,
+			expiry_window: expiry_window_default(),
+			fallback: fallback_default(),
+		}
+	}
+}
+
+impl ConfigCertificates {
+    /// The self-signed certificate `awsl acme` creates for catch-all servers.
+    pub fn self_signed(&self) -> ConfigTls {
+        ConfigTls {
+            certificate: self.fallback.join("fallback.pem"),
+            key: self.fallback.join("fallback.key"),
+            hosts: Map::new(),
+            protocols: Vec::new(),
+            ciphers: None,
+            ocsp_stapling: false,
+            session_tickets: None,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Config {
 	#[serde(default)]
@@ -120,6 +931,39 @@ pub struct Config {
 
 	#[serde(default)]
 	pub templates: Map<String, ConfigServerTemplate>,
+
+	#[serde(default)]
+	pub certificates: ConfigCertificates,
+
+	/// What every http template without a `catchAll` of its own does with
+	/// requests for unknown hosts; nginx's first server takes them if unset.
+	#[serde(default, rename = "catchAll", skip_serializing_if = "Option::is_none")]
+	pub catch_all: Option<ConfigCatchAll>,
+}
+
+impl Config {
+    /// The catch-all server of an http template, if it has one.
+    pub fn catch_all<'a>(&'a self, template: &'a ConfigServerTemplate) -> Option<&'a ConfigCatchAll> {
+        match template {
+            ConfigServerTemplate::Http { catch_all, .. } => catch_all.as_deref().or(self.catch_all.as_ref()),
+            ConfigServerTemplate::Stream { .. } => None,
+        }
+    }
+
+    /// The templates some server uses, in name order.
+    pub fn used_templates(&self) -> impl Iterator<Item = (&String, &ConfigServerTemplate)> {
+        self.templates.iter().filter(move |(name, _)| self.servers.iter().any(|s| s.template == **name))
+    }
+
+    /// Whether a catch-all server or a host awaiting its ACME certificate
+    /// shows the self-signed certificate.
+    pub fn uses_self_signed(&self) -> bool {
+        let self_signed = Some(self.certificates.self_signed());
+        self.used_templates()
+            .filter_map(|(_, t)| self.catch_all(t))
+            .any(|c| c.effective_tls(self) == self_signed)
+            || self.servers.iter().any(|s| s.awaits_issuance(self))
+    }
 }
 
 
@@ -212,6 +1056,1659 @@ where
     deserializer.deserialize_any(StringOrList(PhantomData))
 }
 
-pub fn validate(cfg: &Config) -> Result<(), Box<dyn std::error::Error>> {
-    Ok(())
+fn one_or_list<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    // Like `string_or_list`, but hands scalars of any kind to T's own
+    // `Deserialize` impl, so it also works for numbers such as ports.
+    struct OneOrList<T>(PhantomData<fn() -> Vec<T>>);
+
+    impl<'de, T> Visitor<'de> for OneOrList<T>
+    where
+        T: Deserialize<'de>,
+    {
+        type Value = Vec<T>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("value or sequence")
+        }
+
+        fn visit_u64<E>(self, value: u64) -> Result<Vec<T>, E>
+        where
+            E: de::Error,
+        {
+            Ok(vec![T::deserialize(value.into_deserializer())?])
+        }
+
+        fn visit_i64<E>(self, value: i64) -> Result<Vec<T>, E>
+        where
+            E: de::Error,
+        {
+            Ok(vec![T::deserialize(value.into_deserializer())?])
+        }
+
+        fn visit_str<E>(self, value: &str) -> Result<Vec<T>, E>
+        where
+            E: de::Error,
+        {
+            Ok(vec![T::deserialize(value.into_deserializer())?])
+        }
+
+        fn visit_map<M>(self, map: M) -> Result<Vec<T>, M::Error>
+        where
+            M: MapAccess<'de>,
+        {
+            Ok(vec![T::deserialize(de::value::MapAccessDeserializer::new(map))?])
+        }
+
+        fn visit_seq<S>(self, seq: S) -> Result<Vec<T>, S::Error>
+        where
+            S: SeqAccess<'de>,
+        {
+            Deserialize::deserialize(de::value::SeqAccessDeserializer::new(seq))
+        }
+    }
+
+    deserializer.deserialize_any(OneOrList(PhantomData))
+}
+
+/// A single problem found by `validate`, located by its path in the config.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+fn is_dns_label(label: &str) -> bool {
+    !label.is_empty() && label.len() <= 63
+        && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        && !label.starts_with('-') && !label.ends_with('-')
+}
+
+fn is_dns_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 253 && name.split('.').all(is_dns_label)
+}
+
+/// Accepts DNS names plus the wildcard forms nginx understands in
+/// `server_name`: `*.example.com`, `.example.com` and `example.*`.
+pub fn is_valid_host(host: &str) -> bool {
+    if let Some(rest) = host.strip_prefix("*.") {
+        is_dns_name(rest)
+    } else if let Some(rest) = host.strip_suffix(".*") {
+        is_dns_name(rest)
+    } else if let Some(rest) = host.strip_prefix('.') {
+        is_dns_name(rest)
+    } else {
+        is_dns_name(host)
+    }
+}
+
+fn is_host_port(s: &str) -> bool {
+    let (host, port) = if let Some(rest) = s.strip_prefix('[') {
+        match rest.split_once("]:") {
+            Some((addr, port)) => (addr.parse::<std::net::Ipv6Addr>().is_ok(), port),
+            None => return false,
+        }
+    } else {
+        match s.rsplit_once(':') {
+            Some((host, port)) => (is_dns_name(host), port),
+            None => return false,
+        }
+    };
+    host && matches!(port.parse::<u16>(), Ok(p) if p != 0)
+}
+
+/// Accepts `host:port`, `[v6]:port`, `unix:/path` and
+/// `http(s)://host[:port][/path]`.
+pub fn is_valid_proxy_target(target: &str) -> bool {
+    if let Some(path) = target.strip_prefix("unix:") {
+        return path.starts_with('/');
+    }
+    if let Some((scheme, rest)) = target.split_once("://") {
+        if scheme != "http" && scheme != "https" {
+            return false;
+        }
+        let authority = match rest.find('/') {
+            Some(i) => &rest[..i],
+            None => rest,
+        };
+        return is_host_port(authority) || is_dns_name(authority)
+            || authority.parse::<std::net::Ipv4Addr>().is_ok();
+    }
+    is_host_port(target)
+}
+
+/// Accepts `host:port`, `[v6]:port` and `unix:/path`.
+pub fn is_socket_address(address: &str) -> bool {
+    match address.strip_prefix("unix:") {
+        Some(path) => path.starts_with('/'),
+        None => is_host_port(address),
+    }
+}
+
+/// Accepts what an upstream `server` line takes: `host[:port]`,
+/// `[v6]:port` and `unix:/path`.
+pub fn is_upstream_address(address: &str) -> bool {
+    if let Some(path) = address.strip_prefix("unix:") {
+        return path.starts_with('/');
+    }
+    is_host_port(address) || is_dns_name(address) || address.parse::<std::net::Ipv4Addr>().is_ok()
+}
+
+/// Accepts nginx sizes such as `0`, `512k`, `10m` or `1g`.
+pub fn is_nginx_size(size: &str) -> bool {
+    let digits = size.trim_end_matches(['k', 'K', 'm', 'M', 'g', 'G']);
+    size.len() - digits.len() <= 1 && !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Accepts HTTP field names (RFC 9110 tokens).
+pub fn is_header_name(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// Header values are rendered in double quotes, so they cannot contain
+/// quotes, backslashes or control characters.
+pub fn is_header_value(value: &str) -> bool {
+    !value.contains(|c: char| c == '"' || c == '\\' || c.is_control())
+}
+
+/// Regexes are rendered in double quotes, where nginx keeps a backslash
+/// unless it precedes a quote or another backslash.
+pub fn is_quotable_pattern(pattern: &str) -> bool {
+    !pattern.is_empty() && !pattern.contains(|c: char| c == '"' || c.is_control())
+        && !pattern.contains("\\\\") && !pattern.ends_with('\\')
+}
+
+/// Accepts nginx times such as `30`, `10s`, `500ms` or `1h30m`.
+pub fn is_nginx_time(time: &str) -> bool {
+    let mut rest = time;
+    if rest.is_empty() {
+        return false;
+    }
+    while !rest.is_empty() {
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        if digits == 0 {
+            return false;
+        }
+        rest = &rest[digits..];
+        // "ms" has to be tried before "m"
+        match ["ms", "s", "m", "h", "d", "w", "M", "y"].iter().find(|u| rest.starts_with(*u)) {
+            Some(unit) => rest = &rest[unit.len()..],
+            None => return rest.is_empty(),
+        }
+    }
+    true
+}
+
+pub fn server_path(index: usize, server: &ConfigServer) -> String {
+    match &server.name {
+        Some(name) => format!("servers[{}] ({})", index, name),
+        None => format!("servers[{}]", index),
+    }
+}
+
+fn validate_certificate(path: &str, cert: &Path, key: &Path, out: &mut Vec<Diagnostic>) {
+    if let Err(message) = certificate_names(cert) {
+        out.push(Diagnostic { path: path.to_owned() + ".certificate", message: format!("{:?}: {}", cert, message) });
+    }
+    if !key.is_file() {
+        out.push(Diagnostic { path: path.to_owned() + ".key", message: format!("{:?} is not a file", key) });
+    }
+}
+
+fn validate_tls(path: &str, tls: &ConfigTls, acme: bool, out: &mut Vec<Diagnostic>) {
+    if !acme {
+        validate_certificate(path, &tls.certificate, &tls.key, out);
+    }
+    for (host, cert) in &tls.hosts {
+        validate_certificate(&format!("{}.hosts.{}", path, host), &cert.certificate, &cert.key, out);
+    }
+    for (i, protocol) in tls.protocols.iter().enumerate() {
+        if !TLS_PROTOCOLS.contains(&protocol.as_str()) {
+            out.push(Diagnostic { path: format!("{}.protocols[{}]", path, i), message: format!("unknown protocol {:?}", protocol) });
+        }
+    }
+}
+
+fn validate_template(name: &str, template: &ConfigServerTemplate, out: &mut Vec<Diagnostic>) {
+    let path = format!("templates.{}", name);
+    match template {
+        ConfigServerTemplate::Http { https, port, tls, acme, listen, catch_all } => {
+            if let Some(tls) = tls {
+                validate_tls(&(path.clone() + ".tls"), tls, acme.is_some(), out);
+            }
+            if let Some(acme) = acme {
+                if !acme.directory.starts_with("https://") && !acme.directory.starts_with("http://") {
+                    out.push(Diagnostic { path: path.clone() + ".acme.directory", message: format!("{:?} is not an http(s) URL", acme.directory) });
+                }
+                if matches!(https, ConfigHttpHttps::Disabled) {
+                    out.push(Diagnostic { path: path.clone() + ".acme", message: "ACME certificates need HTTPS enabled".to_owned() });
+                }
+                if !port.http.iter().any(|l| l.port == 80) {
+                    out.push(Diagnostic { path: path.clone() + ".port.http", message: "HTTP-01 challenges arrive on port 80, which is not served".to_owned() });
+                }
+            }
+            if let ConfigHttpHttps::HSTS { duration: 0, .. } = https {
+                out.push(Diagnostic { path: path.clone() + ".https.duration", message: "HSTS duration must be nonzero".to_owned() });
+            }
+            let uses_http = !matches!(https, ConfigHttpHttps::Only);
+            let uses_https = !matches!(https, ConfigHttpHttps::Disabled);
+            if uses_http && port.http.is_empty() {
+                out.push(Diagnostic { path: path.clone() + ".port.http", message: "no HTTP port given".to_owned() });
+            }
+            if uses_https && port.https.is_empty() {
+                out.push(Diagnostic { path: path.clone() + ".port.https", message: "no HTTPS port given".to_owned() });
+            }
+            if uses_http && uses_https {
+                for p in port.http.iter().filter(|p| port.https.contains(p)) {
+                    out.push(Diagnostic { path: path.clone() + ".port", message: format!("port {} is used for both HTTP and HTTPS", p) });
+                }
+            }
+            validate_listen_options(&(path.clone() + ".listen"), uses_https, port, listen, out);
+            if let Some(catch_all) = catch_all {
+                validate_catch_all(&(path.clone() + ".catchAll"), catch_all, out);
+            }
+        },
+        ConfigServerTemplate::Stream { protocol, port, tls, preread } => {
+            if port.is_empty() {
+                out.push(Diagnostic { path: path.clone() + ".port", message: "no port given".to_owned() });
+            }
+            if let Some(tls) = tls {
+                validate_tls(&(path.clone() + ".tls"), tls, false, out);
+                if *preread {
+                    out.push(Diagnostic { path: path.clone() + ".preread", message: "connections are either terminated or passed through, not both".to_owned() });
+                }
+            }
+            if *protocol == ConfigStreamProtocol::Udp {
+                if tls.is_some() {
+                    out.push(Diagnostic { path: path.clone() + ".tls", message: "TLS cannot be terminated over UDP".to_owned() });
+                }
+                if *preread {
+                    out.push(Diagnostic { path: path.clone() + ".preread", message: "SNI names are only read from TCP connections".to_owned() });
+                }
+            }
+        },
+    }
+}
+
+/// Checks the servers of an upstream and how it balances between them;
+/// `address` describes what is wrong with an address, if anything.
+fn validate_upstream<R, A>(target: &[ConfigUpstreamServer], balance: &ConfigBalance, report: &mut R, address: A)
+where
+    R: FnMut(&str, String),
+    A: Fn(&str) -> Option<String>,
+{
+    for (i, t) in target.iter().enumerate() {
+        let field = format!("target[{}]", i);
+        if let Some(message) = address(&t.server) {
+            report(&field, message);
+        }
+        if t.weight == Some(0) {
+            report(&(field.clone() + ".weight"), "weight must be positive".to_owned());
+        }
+        if let Some(timeout) = &t.fail_timeout {
+            if !is_nginx_time(timeout) {
+                report(&(field.clone() + ".failTimeout"), format!("{:?} is not a time such as 10s", timeout));
+            }
+        }
+        if t.backup && matches!(balance, ConfigBalance::IpHash | ConfigBalance::Hash { .. }) {
+            report(&(field.clone() + ".backup"), "backup servers cannot be used with hash balancing".to_owned());
+        }
+    }
+    if let ConfigBalance::Hash { key, .. } = balance {
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            report("balance.hash.key", format!("{:?} is not a hash key", key));
+        }
+    }
+}
+
+fn validate_backend(path: &str, location: Option<&str>, backend: &ConfigBackend, out: &mut Vec<Diagnostic>) {
+    let mut report = |field: &str, message: String| out.push(Diagnostic {
+        path: format!("{}.backend.{}", path, field),
+        message,
+    });
+    match backend {
+        ConfigBackend::Proxy { target, balance, options } => {
+            let single = target.len() == 1 && target[0].is_plain() && balance.is_round_robin();
+            if target.is_empty() {
+                report("target", "no target given".to_owned());
+            } else if single && !is_valid_proxy_target(&target[0].server) {
+                report("target", format!("{:?} is neither host:port nor a URL", target[0].server));
+            }
+            if !single {
+                validate_upstream(target, balance, &mut report, |address| (!is_upstream_address(address))
+                    .then(|| format!("{:?} is neither host[:port] nor unix:/path", address)));
+            }
+            for (field, headers) in [("requestHeaders", &options.request_headers), ("responseHeaders", &options.response_headers)] {
+                for (name, value) in headers {
+                    let field = format!("{}.{}", field, name);
+                    if !is_header_name(name) {
+                        report(&field, format!("{:?} is not a header name", name));
+                    } else if !is_header_value(value) {
+                        report(&field, format!("{:?} cannot be used as a header value", value));
+                    } else if options.websocket && field.starts_with("request")
+                        && (name.eq_ignore_ascii_case("upgrade") || name.eq_ignore_ascii_case("connection")) {
+                        report(&field, "set by websocket: true".to_owned());
+                    }
+                }
+            }
+            for (field, timeout) in [("connectTimeout", &options.connect_timeout), ("readTimeout", &options.read_timeout), ("sendTimeout", &options.send_timeout)] {
+                if let Some(timeout) = timeout {
+                    if !is_nginx_time(timeout) {
+                        report(field, format!("{:?} is not a time such as 10s", timeout));
+                    }
+                }
+            }
+            if let Some(size) = &options.max_body_size {
+                if !is_nginx_size(size) {
+                    report("maxBodySize", format!("{:?} is not a size such as 10m", size));
+                }
+            }
+        },
+        ConfigBackend::Passthrough { target, balance } => {
+            let single = target.len() == 1 && target[0].is_plain() && balance.is_round_robin();
+            let address = |address: &str| (!is_socket_address(address))
+                .then(|| format!("{:?} is not host:port or unix:/path", address));
+            if target.is_empty() {
+                report("target", "no target given".to_owned());
+            } else if single {
+                if let Some(message) = address(&target[0].server) {
+                    report("target", message);
+                }
+            } else {
+                validate_upstream(target, balance, &mut report, address);
+            }
+            if *balance == ConfigBalance::IpHash {
+                report("balance", "ipHash is HTTP only; hash on $remote_addr instead".to_owned());
+            }
+        },
+        ConfigBackend::Rewrite { target, code, options } => {
+            if target.is_empty() || target.contains(char::is_whitespace) {
+                report("target", format!("{:?} is not a URL or URI", target));
+            } else if options.internal && !target.starts_with('/') {
+                report("target", format!("{:?} is not a URI, which internal rewrites need", target));
+            }
+            // internal rewrites send no status
+            if !options.internal {
+                if !(300..400).contains(code) {
+                    report("code", format!("{} is not a 3xx redirect status", code));
+                } else if options.keep_method && *code != 301 && *code != 302 {
+                    report("keepMethod", format!("only applies to 301 and 302, not {}", code));
+                }
+            }
+            if let Some(pattern) = &options.pattern {
+                if !is_quotable_pattern(pattern) {
+                    report("pattern", format!("{:?} cannot be used as a pattern", pattern));
+                }
+                if options.preserve_path {
+                    report("preservePath", "use captures of pattern instead".to_owned());
+                }
+            }
+        },
+        ConfigBackend::File { path, options } => {
+            if !path.is_absolute() {
+                report("path", format!("{:?} is not an absolute path", path));
+            }
+            // nginx maps "/img/x" with "alias /srv/img" to "/srv/imgx"
+            let location = location.unwrap_or("/");
+            if options.alias && location.ends_with('/') != path.to_string_lossy().ends_with('/') {
+                report("path", format!("alias {:?} and location {:?} must both end with '/' or neither", path, location));
+            }
+            for (i, file) in options.index.iter().enumerate() {
+                if file.is_empty() || file.contains(|c: char| c.is_whitespace() || c == ';') {
+                    report(&format!("index[{}]", i), format!("{:?} is not a file name", file));
+                }
+            }
+            if options.autoindex_format.is_some() && !options.autoindex {
+                report("autoindexFormat", "autoindex is off".to_owned());
+            }
+            if let Some(fallback) = &options.fallback {
+                if !options.try_files.is_empty() {
+                    report("fallback", "cannot be combined with tryFiles".to_owned());
+                } else if !fallback.starts_with('/') || fallback.contains(char::is_whitespace) {
+                    report("fallback", format!("{:?} is not an absolute URI", fallback));
+                }
+            }
+            for (i, file) in options.try_files.iter().enumerate() {
+                if file.is_empty() || file.contains(|c: char| c.is_whitespace() || c == ';') {
+                    report(&format!("tryFiles[{}]", i), format!("{:?} is not a try_files entry", file));
+                }
+            }
+            for (extension, expires) in &options.expires {
+                if extension.is_empty() || !extension.bytes().all(|b| b.is_ascii_alphanumeric()) {
+                    report(&format!("expires.{}", extension), format!("{:?} is not a file extension", extension));
+                } else if !(is_nginx_time(expires) || ["off", "epoch", "max"].contains(&expires.as_str())) {
+                    report(&format!("expires.{}", extension), format!("{:?} is not a time, off, epoch or max", expires));
+                }
+            }
+        },
+        ConfigBackend::FastCgi { target, root, index, front_controller, params } => {
+            if !is_socket_address(target) {
+                report("target", format!("{:?} is neither host:port nor unix:/path", target));
+            }
+            if !root.is_absolute() {
+                report("root", format!("{:?} is not an absolute path", root));
+            }
+            if index.is_empty() || index.contains(|c: char| c == '/' || c.is_whitespace()) {
+                report("index", format!("{:?} is not a file name", index));
+            }
+            if let Some(front) = front_controller {
+                if !front.starts_with('/') || front.contains(char::is_whitespace) {
+                    report("frontController", format!("{:?} is not an absolute URI", front));
+                }
+            }
+            validate_params(params, report);
+        },
+        ConfigBackend::Uwsgi { target, params, .. } => {
+            let address = target.strip_prefix("uwsgi://").or_else(|| target.strip_prefix("suwsgi://")).unwrap_or(target);
+            if !is_socket_address(address) {
+                report("target", format!("{:?} is neither host:port nor unix:/path", target));
+            }
+            validate_params(params, report);
+        },
+        ConfigBackend::Scgi { target, params } => {
+            if !is_socket_address(target) {
+                report("target", format!("{:?} is neither host:port nor unix:/path", target));
+            }
+            validate_params(params, report);
+        },
+        ConfigBackend::Grpc { target, errors } => {
+            let address = target.strip_prefix("grpc://").or_else(|| target.strip_prefix("grpcs://")).unwrap_or(target);
+            if !is_socket_address(address) {
+                report("target", format!("{:?} is neither host:port nor unix:/path", target));
+            }
+            for (status, code) in errors {
+                if !(400..600).contains(status) {
+                    report(&format!("errors.{}", status), format!("{} is not an HTTP error status", status));
+                } else if *code > 16 {
+                    report(&format!("errors.{}", status), format!("{} is not a gRPC status code", code));
+                }
+            }
+        },
+    }
+}
+
+fn validate_params(params: &Map<String, String>, mut report: impl FnMut(&str, String)) {
+    for (name, value) in params {
+        if name.is_empty() || !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') {
+            report(&format!("params.{}", name), format!("{:?} is not a parameter name", name));
+        } else if !is_header_value(value) {
+            report(&format!("params.{}", name), format!("{:?} cannot be used as a parameter value", value));
+        }
+    }
+}
+
+/// Whether the `server_name` entry `name` matches `host`.
+fn host_matches(name: &str, host: &str) -> bool {
+    if let Some(suffix) = name.strip_prefix('*') {
+        host.ends_with(suffix)
+    } else if let Some(prefix) = name.strip_suffix('*') {
+        host.starts_with(prefix)
+    } else if let Some(rest) = name.strip_prefix('.') {
+        host == rest || host.ends_with(name)
+    } else {
+        name == host
+    }
+}
+
+/// Whether `server` answers requests over `scheme` on `port`; an HTTPS
+/// redirect on the HTTP ports counts, as it ends up at the server anyway.
+fn serves(cfg: &Config, server: &ConfigServer, scheme: Option<&str>, port: Option<u16>) -> bool {
+    let (https, ports) = match cfg.templates.get(&server.template) {
+        Some(ConfigServerTemplate::Http { https, port, .. }) => (https, port),
+        _ => return false,
+    };
+    match scheme {
+        None => true,
+        Some("http") => !matches!(https, ConfigHttpHttps::Only) && ports.http.iter().any(|l| l.port == port.unwrap_or(80)),
+        Some(_) => !matches!(https, ConfigHttpHttps::Disabled) && ports.https.iter().any(|l| l.port == port.unwrap_or(443)),
+    }
+}
+
+/// The server whose location is the longest prefix of `path` on `host`.
+fn resolve(cfg: &Config, host: &str, path: &str, scheme: Option<&str>, port: Option<u16>) -> Option<usize> {
+    let location = |s: &ConfigServer| s.location.clone().unwrap_or_else(|| "/".to_owned());
+    cfg.servers.iter().enumerate()
+        .filter(|(_, s)| s.host.iter().any(|h| host_matches(h, host)) && serves(cfg, s, scheme, port))
+        .filter(|(_, s)| path.starts_with(&location(s)))
+        .max_by_key(|(_, s)| location(s).len())
+        .map(|(i, _)| i)
+}
+
+/// Whether the rewrite of `server` sends requests straight back to it.
+/// Targets built from captures cannot be followed and are left alone.
+fn rewrite_loops(cfg: &Config, index: usize, server: &ConfigServer) -> bool {
+    let (target, options) = match &server.backend {
+        ConfigBackend::Rewrite { target, options, .. } if options.pattern.is_none() => (target, options),
+        _ => return false,
+    };
+    let (scheme, rest) = match target.split_once("://") {
+        Some((scheme, rest)) if scheme == "http" || scheme == "https" => (Some(scheme), rest),
+        Some(_) => return false,
+        None => (None, target.as_str()),
+    };
+    let (authority, path) = match scheme {
+        Some(_) => match rest.find('/') {
+            Some(i) => (Some(&rest[..i]), &rest[i..]),
+            None => (Some(rest), "/"),
+        },
+        None => (None, rest),
+    };
+    if !path.starts_with('/') || authority.is_some_and(|a| a.contains('$')) {
+        return false;
+    }
+    // only the fixed part of the path says where the request goes
+    let mut path = path[..path.find(['$', '?']).unwrap_or(path.len())].to_owned();
+    if options.preserve_path {
+        path = path.trim_end_matches('/').to_owned() + server.location.as_deref().unwrap_or("/");
+    }
+    match authority {
+        Some(authority) => {
+            let (host, port) = match authority.rsplit_once(':') {
+                Some((host, port)) if !port.contains(']') => match port.parse() {
+                    Ok(port) => (host, Some(port)),
+                    Err(_) => return false,
+                },
+                _ => (authority, None),
+            };
+            resolve(cfg, &host.to_ascii_lowercase(), &path, scheme, port) == Some(index)
+        },
+        None => server.host.iter().any(|host| resolve(cfg, host, &path, None, None) == Some(index)),
+    }
+}
+
+/// What an http template listens on.
+fn http_listens<'a>(https: &ConfigHttpHttps, port: &'a ConfigHttpPort) -> Vec<&'a ConfigListen> {
+    let mut listens = Vec::new();
+    if !matches!(https, ConfigHttpHttps::Only) {
+        listens.extend(&port.http);
+    }
+    if !matches!(https, ConfigHttpHttps::Disabled) {
+        listens.extend(&port.https);
+    }
+    listens
+}
+
+/// The TCP ports an http template listens on, on any address.
+fn http_ports(https: &ConfigHttpHttps, port: &ConfigHttpPort) -> Vec<u16> {
+    http_listens(https, port).into_iter().filter(|l| !l.is_unix()).map(|l| l.port).collect()
+}
+
+/// An address or CIDR range `set_real_ip_from` takes, or `unix:`.
+fn is_address_range(range: &str) -> bool {
+    let (address, prefix) = match range.split_once('/') {
+        Some((address, prefix)) => (address, Some(prefix)),
+        None => (range, None),
+    };
+    let max = match address.parse::<std::net::IpAddr>() {
+        Ok(std::net::IpAddr::V4(_)) => 32,
+        Ok(std::net::IpAddr::V6(_)) => 128,
+        Err(_) => return range == "unix:",
+    };
+    prefix.is_none_or(|p| p.parse::<u8>().is_ok_and(|p| p <= max))
+}
+
+fn validate_listen_options(path: &str, uses_https: bool, port: &ConfigHttpPort, listen: &ConfigListenOptions, out: &mut Vec<Diagnostic>) {
+    if !uses_https {
+        for (on, field, protocol) in &[(listen.http2, "http2", "HTTP/2"), (listen.http3, "http3", "HTTP/3")] {
+            if *on {
+                out.push(Diagnostic { path: format!("{}.{}", path, field), message: format!("{} needs HTTPS enabled", protocol) });
+            }
+        }
+    } else if listen.http3 && port.https.iter().any(|l| l.is_unix()) {
+        out.push(Diagnostic { path: path.to_owned() + ".http3", message: "QUIC cannot listen on a unix socket".to_owned() });
+    }
+    for (i, range) in listen.proxy_protocol.iter().flatten().enumerate() {
+        if !is_address_range(range) {
+            out.push(Diagnostic { path: format!("{}.proxyProtocol[{}]", path, i), message: format!("{:?} is not an address or CIDR range", range) });
+        }
+    }
+}
+
+fn validate_catch_all(path: &str, catch_all: &ConfigCatchAll, out: &mut Vec<Diagnostic>) {
+    match &catch_all.action {
+        ConfigCatchAllAction::Close => {},
+        ConfigCatchAllAction::Page { status, body } => {
+            if !(200..=599).contains(status) {
+                out.push(Diagnostic { path: path.to_owned() + ".status", message: format!("{} is not an HTTP status", status) });
+            }
+            // rendered in double quotes, like header values
+            if !is_header_value(body) {
+                out.push(Diagnostic { path: path.to_owned() + ".body", message: "body cannot contain double quotes, backslashes or control characters".to_owned() });
+            }
+        },
+        ConfigCatchAllAction::Redirect { target } => {
+            if !is_dns_name(target) && !is_host_port(target) {
+                out.push(Diagnostic { path: path.to_owned() + ".target", message: format!("{:?} is not a host name", target) });
+            }
+        },
+    }
+    if let Some(tls) = &catch_all.tls {
+        validate_tls(&(path.to_owned() + ".tls"), tls, false, out);
+    }
+}
+
+/// nginx takes one default server per address:port, which is either a
+/// template's own or a catch-all server; templates sharing an address:port
+/// can share a catch-all. Reported once, on the later template.
+fn validate_default_servers(cfg: &Config, out: &mut Vec<Diagnostic>) {
+    let mut defaults: Vec<(&String, Vec<&ConfigListen>, Option<&ConfigCatchAll>)> = Vec::new();
+    for (name, template) in &cfg.templates {
+        let (https, port, listen) = match template {
+            ConfigServerTemplate::Http { https, port, listen, .. } => (https, port, listen),
+            ConfigServerTemplate::Stream { .. } => continue,
+        };
+        let catch_all = cfg.catch_all(template);
+        if listen.default_server && catch_all.is_some() {
+            out.push(Diagnostic { path: format!("templates.{}.listen.defaultServer", name), message: "the catch-all server is the default server".to_owned() });
+        } else if listen.default_server || catch_all.is_some() {
+            defaults.push((name, http_listens(https, port), catch_all));
+        }
+    }
+    for (i, (name, listens, catch_all)) in defaults.iter().enumerate() {
+        for (other, others, other_catch_all) in &defaults[..i] {
+            if catch_all.is_some() && catch_all == other_catch_all {
+                continue;
+            }
+            let field = if catch_all.is_some() { "catchAll" } else { "listen.defaultServer" };
+            for listen in listens.iter().filter(|l| others.contains(l)) {
+                out.push(Diagnostic { path: format!("templates.{}.{}", name, field), message: format!("{} is also the default server of template {:?}", listen, other) });
+            }
+        }
+    }
+}
+
+/// A stream template owns its ports: http templates cannot listen on them,
+/// and other stream templates can only share them to route by SNI too.
+fn validate_ports(cfg: &Config, out: &mut Vec<Diagnostic>) {
+    for (name, template) in &cfg.templates {
+        let (protocol, ports, preread) = match template {
+            ConfigServerTemplate::Stream { protocol, port, preread, .. } => (protocol, port, preread),
+            _ => continue,
+        };
+        for port in ports {
+            for (other, template) in &cfg.templates {
+                let clash = match template {
+                    ConfigServerTemplate::Http { https, port: http, .. } =>
+                        *protocol == ConfigStreamProtocol::Tcp && http_ports(https, http).contains(port),
+                    // reported once, on the later template
+                    ConfigServerTemplate::Stream { protocol: p, port: ports, preread: shared, .. } =>
+                        other < name && p == protocol && ports.contains(port) && !(*preread && *shared),
+                };
+                if clash {
+                    let module = if matches!(template, ConfigServerTemplate::Http { .. }) { "http" } else { "stream" };
+                    out.push(Diagnostic { path: format!("templates.{}.port", name), message: format!("port {} is also used by {} template {:?}", port, module, other) });
+                }
+            }
+        }
+    }
+}
+
+/// What a stream server adds to the checks of `validate_backend`: only
+/// proxies to socket addresses, and none of the HTTP options.
+fn validate_stream_backend(path: &str, preread: bool, backend: &ConfigBackend, out: &mut Vec<Diagnostic>) {
+    let mut report = |field: &str, message: String| out.push(Diagnostic {
+        path: format!("{}.backend.{}", path, field),
+        message,
+    });
+    let (target, balance, options) = match backend {
+        ConfigBackend::Proxy { target, balance, options } => (target, balance, options),
+        _ => return report("type", "stream servers can only proxy".to_owned()),
+    };
+    for (i, t) in target.iter().enumerate() {
+        // anything that is not even valid for HTTP is reported already
+        if !is_socket_address(&t.server) && (is_upstream_address(&t.server) || is_valid_proxy_target(&t.server)) {
+            let field = if target.len() == 1 { "target".to_owned() } else { format!("target[{}]", i) };
+            report(&field, format!("{:?} is not host:port or unix:/path", t.server));
+        }
+    }
+    if *balance == ConfigBalance::IpHash {
+        report("balance", "ipHash is HTTP only; hash on $remote_addr instead".to_owned());
+    }
+    let http_only = [
+        ("forwarded", !options.forwarded.is_empty()),
+        ("requestHeaders", !options.request_headers.is_empty()),
+        ("responseHeaders", !options.response_headers.is_empty()),
+        ("sendTimeout", options.send_timeout.is_some()),
+        ("buffering", options.buffering.is_some()),
+        ("maxBodySize", options.max_body_size.is_some()),
+        ("websocket", options.websocket),
+    ];
+    for (field, _) in http_only.iter().filter(|(_, set)| *set) {
+        report(field, "does not apply to stream servers".to_owned());
+    }
+    if preread {
+        for (field, set) in [("connectTimeout", options.connect_timeout.is_some()), ("readTimeout", options.read_timeout.is_some())] {
+            if set {
+                report(field, "SNI routes share the timeouts of their listener".to_owned());
+            }
+        }
+    }
+}
+
+fn validate_stream_server(path: &str, preread: bool, server: &ConfigServer, out: &mut Vec<Diagnostic>) {
+    if preread && server.host.is_empty() {
+        out.push(Diagnostic { path: path.to_owned() + ".host", message: "no SNI name given".to_owned() });
+    } else if !preread && !server.host.is_empty() {
+        out.push(Diagnostic { path: path.to_owned() + ".host", message: "only stream templates with preread route by host".to_owned() });
+    }
+    for (i, host) in server.host.iter().enumerate() {
+        if !is_valid_host(host) {
+            out.push(Diagnostic { path: format!("{}.host[{}]", path, i), message: format!("{:?} is not a valid host name", host) });
+        }
+    }
+    if server.location.is_some() {
+        out.push(Diagnostic { path: path.to_owned() + ".location", message: "stream servers have no locations".to_owned() });
+    }
+    if server.tls.is_some() {
+        out.push(Diagnostic { path: path.to_owned() + ".tls", message: "stream servers take TLS settings from their template".to_owned() });
+    }
+    validate_backend(path, None, &server.backend, out);
+    validate_stream_backend(path, preread, &server.backend, out);
+}
+
+/// A passthrough takes its hosts whole, on HTTPS ports that plain HTTP
+/// only redirects to, and its target holds the certificates.
+fn validate_passthrough(cfg: &Config, path: &str, server: &ConfigServer, out: &mut Vec<Diagnostic>) {
+    if matches!(server.location.as_deref(), Some(location) if location != "/") {
+        out.push(Diagnostic { path: path.to_owned() + ".location", message: "passthrough takes the whole host".to_owned() });
+    }
+    if let Some(ConfigServerTemplate::Http { https, port, .. }) = cfg.templates.get(&server.template) {
+        if !matches!(https, ConfigHttpHttps::Only | ConfigHttpHttps::Enforcing) {
+            out.push(Diagnostic { path: path.to_owned() + ".backend.type", message: "passthrough needs a template with https: only or enforcing".to_owned() });
+        }
+        // the SNI of a unix socket can only be read by an HTTPS server
+        if port.https.iter().any(|l| l.is_unix()) {
+            out.push(Diagnostic { path: path.to_owned() + ".template", message: "passthrough cannot listen on a unix socket".to_owned() });
+        }
+    }
+    if server.tls.is_some() {
+        out.push(Diagnostic { path: path.to_owned() + ".tls", message: "passed-through connections are not terminated".to_owned() });
+    } else if server.acme(cfg).is_some() {
+        out.push(Diagnostic { path: path.to_owned() + ".backend.type", message: "passed-through hosts cannot take ACME certificates".to_owned() });
+    }
+}
+
+fn validate_server(cfg: &Config, index: usize, server: &ConfigServer, out: &mut Vec<Diagnostic>) {
+    let path = server_path(index, server);
+    match cfg.templates.get(&server.template) {
+        None => out.push(Diagnostic { path: path.clone() + ".template", message: format!("unknown template {:?}", server.template) }),
+        Some(ConfigServerTemplate::Stream { preread, .. }) => return validate_stream_server(&path, *preread, server, out),
+        Some(ConfigServerTemplate::Http { .. }) => {},
+    }
+    if server.host.is_empty() {
+        out.push(Diagnostic { path: path.clone() + ".host", message: "host is empty list".to_owned() });
+    }
+    for (i, host) in server.host.iter().enumerate() {
+        if !is_valid_host(host) {
+            out.push(Diagnostic { path: format!("{}.host[{}]", path, i), message: format!("{:?} is not a valid host name", host) });
+        }
+    }
+    if let Some(location) = &server.location {
+        if !location.starts_with('/') {
+            out.push(Diagnostic { path: path.clone() + ".location", message: format!("{:?} does not start with '/'", location) });
+        }
+    }
+    validate_backend(&path, server.location.as_deref(), &server.backend, out);
+    if rewrite_loops(cfg, index, server) {
+        out.push(Diagnostic { path: path.clone() + ".backend.target", message: "rewrites back to this server".to_owned() });
+    }
+    // the rest concerns certificates, which passed-through hosts bring along
+    if let ConfigBackend::Passthrough { .. } = server.backend {
+        return validate_passthrough(cfg, &path, server, out);
+    }
+
+    if let Some(tls) = &server.tls {
+        validate_tls(&(path.clone() + ".tls"), tls, false, out);
+        for host in tls.hosts.keys().filter(|h| !server.host.contains(h)) {
+            out.push(Diagnostic { path: format!("{}.tls.hosts.{}", path, host), message: "not one of the server's hosts".to_owned() });
+        }
+    }
+    let uses_https = match cfg.templates.get(&server.template) {
+        Some(ConfigServerTemplate::Http { https, .. }) => !matches!(https, ConfigHttpHttps::Disabled),
+        _ => false,
+    };
+    if server.acme(cfg).is_some() {
+        for (i, host) in server.host.iter().enumerate() {
+            if host.contains('*') || host.starts_with('.') {
+                out.push(Diagnostic { path: format!("{}.host[{}]", path, i), message: format!("{:?} cannot be validated over HTTP-01", host) });
+            }
+        }
+    }
+    if uses_https {
+        if server.effective_tls(cfg).is_none() && server.acme(cfg).is_none() {
+            out.push(Diagnostic { path: path.clone() + ".tls", message: "HTTPS needs tls or acme".to_owned() });
+        }
+        for (i, host) in server.host.iter().enumerate() {
+            // until it is issued the host shows the self-signed stand-in, which covers no names
+            if server.acme(cfg).is_some_and(|acme| !acme.is_issued(host)) {
+                continue;
+            }
+            let cert = match server.host_tls(cfg, host) {
+                Some(tls) => tls.certificate,
+                None => continue,
+            };
+            // unreadable certificates are reported where they are configured
+            if let Ok(names) = certificate_names(&cert) {
+                if !names_cover(&names, host) {
+                    out.push(Diagnostic { path: format!("{}.host[{}]", path, i), message: format!("{:?} is not covered by certificate {:?}", host, cert) });
+                }
+            }
+        }
+    }
+}
+
+/// Checks the semantics serde cannot express, reporting every problem found.
+pub fn validate(cfg: &Config) -> Result<(), Error> {
+    let mut diagnostics = Vec::new();
+    for (name, template) in &cfg.templates {
+        validate_template(name, template, &mut diagnostics);
+    }
+    validate_ports(cfg, &mut diagnostics);
+    if let Some(catch_all) = &cfg.catch_all {
+        validate_catch_all("catchAll", catch_all, &mut diagnostics);
+    }
+    validate_default_servers(cfg, &mut diagnostics);
+    for (index, server) in cfg.servers.iter().enumerate() {
+        validate_server(cfg, index, server, &mut diagnostics);
+    }
+    if let Some(directory) = &cfg.certificates.directory {
+        if !directory.is_dir() {
+            diagnostics.push(Diagnostic { path: "certificates.directory".to_owned(), message: format!("{:?} is not a directory", directory) });
+        }
+    }
+
+    if diagnostics.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Validation(diagnostics))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(yaml: &str) -> Config {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn diagnostics(cfg: &Config) -> Vec<Diagnostic> {
+        match validate(cfg) {
+            Err(Error::Validation(d)) => d,
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    fn diagnostic_paths(diagnostics: &[Diagnostic]) -> Vec<&str> {
+        diagnostics.iter().map(|d| d.path.as_str()).collect()
+    }
+
+    #[test]
+    fn validate_accepts_good_config() {
+        let cfg = parse("
+templates:
+  web:
+    module: http
+    https:
+      hsts:
+        duration: 31536000
+    port: {}
+    tls:
+      certificate: tests/fixtures/tls/example.com.pem
+      key: tests/fixtures/tls/example.com.key
+servers:
+  - template: web
+    host: [example.com, '*.example.com', '.example.com', 'www.example.*']
+    location: /api
+    backend:
+      type: proxy
+      target: 127.0.0.1:8080
+  - template: web
+    host: example.com
+    location: /old
+    backend:
+      type: rewrite
+      target: https://example.com/new
+      code: 308
+  - template: web
+    host: static.example.com
+    backend: /srv/static
+  - template: web
+    host: up.example.com
+    backend:
+      type: proxy
+      target: https://[::1]:8443/prefix
+");
+        validate(&cfg).unwrap();
+    }
+
+    #[test]
+    fn validate_collects_every_error() {
+        let cfg = parse("
+templates:
+  web:
+    module: http
+    https:
+      hsts:
+        duration: 0
+    port: {}
+    tls:
+      certificate: tests/fixtures/tls/example.com.pem
+      key: tests/fixtures/tls/example.com.key
+servers:
+  - name: broken
+    template: nope
+    host: [-bad.example.com, 'a..b', 'x.*.y']
+    location: api
+    backend:
+      type: proxy
+      target: 127.0.0.1
+  - template: web
+    host: []
+    backend:
+      type: rewrite
+      target: /x
+      code: 200
+  - template: web
+    host: example.com
+    backend: relative/path
+");
+        let diagnostics = diagnostics(&cfg);
+        let paths = diagnostic_paths(&diagnostics);
+        assert_eq!(paths, vec![
+            "templates.web.https.duration",
+            "servers[0] (broken).template",
+            "servers[0] (broken).host[0]",
+            "servers[0] (broken).host[1]",
+            "servers[0] (broken).host[2]",
+            "servers[0] (broken).location",
+            "servers[0] (broken).backend.target",
+            "servers[1].host",
+            "servers[1].backend.code",
+            "servers[2].backend.path",
+        ]);
+    }
+
+    #[test]
+    fn config_http_ports() {
+        let cfg = parse("
+templates:
+  default:
+    module: http
+    https: compatible
+  single:
+    module: http
+    https: compatible
+    port:
+      http: 8080
+  list:
+    module: http
+    https: compatible
+    port:
+      http: [80, 8080]
+      https: [443, 8443]
+  addresses:
+    module: http
+    https: compatible
+    port:
+      http: ['10.0.0.1:80', '*:8080', 'unix:/run/awsl/http.sock']
+      https: '[::]:443'
+");
+        let ports = |name: &str| match &cfg.templates[name] {
+            ConfigServerTemplate::Http { port, .. } => (port.http.iter().map(|l| l.port).collect(), port.https.iter().map(|l| l.port).collect()),
+            ConfigServerTemplate::Stream { .. } => unreachable!(),
+        };
+        assert_eq!(ports("default"), (vec![80], vec![443]));
+        assert_eq!(ports("single"), (vec![8080], vec![443]));
+        assert_eq!(ports("list"), (vec![80, 8080], vec![443, 8443]));
+        assert_eq!(ports("addresses"), (vec![80, 8080, 0], vec![443]));
+        let listens = |name: &str| match &cfg.templates[name] {
+            ConfigServerTemplate::Http { port, .. } => port.http.iter().chain(&port.https).map(|l| l.to_string()).collect::<Vec<_>>(),
+            ConfigServerTemplate::Stream { .. } => unreachable!(),
+        };
+        assert_eq!(listens("addresses"), vec!["10.0.0.1:80", "*:8080", "unix:/run/awsl/http.sock", "[::]:443"]);
+        validate(&cfg).unwrap();
+
+        for bad in &["0", "70000", "example.com:80", "10.0.0.1", "::1:80", "[::1]", "unix:run.sock"] {
+            assert!(serde_yaml::from_str::<ConfigListen>(bad).is_err(), "{} parsed", bad);
+        }
+        assert_eq!(serde_json::to_string(&vec![ConfigListen::from(80), "[::1]:80".parse().unwrap()]).unwrap(), r#"[80,"[::1]:80"]"#);
+    }
+
+    #[test]
+    fn validate_http_ports() {
+        let cfg = parse("
+templates:
+  overlap:
+    module: http
+    https: compatible
+    port:
+      http: [80, 443]
+  empty:
+    module: http
+    https: only
+    port:
+      http: []
+      https: []
+  unused:
+    module: http
+    https: disabled
+    port:
+      https: [80]
+");
+        let diagnostics = diagnostics(&cfg);
+        assert_eq!(diagnostics, vec![
+            Diagnostic { path: "templates.empty.port.https".to_owned(), message: "no HTTPS port given".to_owned() },
+            Diagnostic { path: "templates.overlap.port".to_owned(), message: "port 443 is used for both HTTP and HTTPS".to_owned() },
+        ]);
+    }
+
+    #[test]
+    fn validate_listen_options() {
+        let cfg = parse("
+templates:
+  edge:
+    module: http
+    https: compatible
+    listen: {http2: true, http3: true, defaultServer: true, proxyProtocol: [10.0.0.0/8, '::1', 'unix:']}
+  plain:
+    module: http
+    https: disabled
+    listen: {http2: true, http3: true, defaultServer: true, proxyProtocol: [10.0.0.0/33, example.com]}
+  socket:
+    module: http
+    https: only
+    port: {https: [443, 'unix:/run/awsl.sock']}
+    listen: {http3: true, defaultServer: true}
+");
+        let diagnostics = diagnostics(&cfg);
+        let messages: Vec<String> = diagnostics.iter().map(|d| format!("{}: {}", d.path, d.message)).collect();
+        assert_eq!(messages, vec![
+            "templates.plain.listen.http2: HTTP/2 needs HTTPS enabled",
+            "templates.plain.listen.http3: HTTP/3 needs HTTPS enabled",
+            "templates.plain.listen.proxyProtocol[0]: \"10.0.0.0/33\" is not an address or CIDR range",
+            "templates.plain.listen.proxyProtocol[1]: \"example.com\" is not an address or CIDR range",
+            "templates.socket.listen.http3: QUIC cannot listen on a unix socket",
+            "templates.plain.listen.defaultServer: 80 is also the default server of template \"edge\"",
+            "templates.socket.listen.defaultServer: 443 is also the default server of template \"edge\"",
+        ]);
+    }
+
+    #[test]
+    fn validate_catch_all() {
+        let cfg = parse("
+catchAll:
+  type: page
+  status: 700
+  body: <a href=\"/\">home</a>
+templates:
+  web:
+    module: http
+    https: compatible
+  own:
+    module: http
+    https: only
+    catchAll: {type: redirect, target: 'https://example.com'}
+  default:
+    module: http
+    https: only
+    listen: {defaultServer: true}
+  internal:
+    module: http
+    https: disabled
+    port: {http: 8080}
+    catchAll: {type: close}
+");
+        let diagnostics = diagnostics(&cfg);
+        let messages: Vec<String> = diagnostics.iter().map(|d| format!("{}: {}", d.path, d.message)).collect();
+        assert_eq!(messages, vec![
+            "templates.own.catchAll.target: \"https://example.com\" is not a host name",
+            "catchAll.status: 700 is not an HTTP status",
+            "catchAll.body: body cannot contain double quotes, backslashes or control characters",
+            "templates.default.listen.defaultServer: the catch-all server is the default server",
+            "templates.web.catchAll: 443 is also the default server of template \"own\"",
+        ]);
+
+        let cfg = parse("
+catchAll: {type: close}
+templates:
+  a: {module: http, https: only, tls: {certificate: tests/fixtures/tls/example.com.pem, key: tests/fixtures/tls/example.com.key}}
+  b: {module: http, https: only, port: {https: [443, 8443]}}
+  c: {module: http, https: only, catchAll: {type: page}, port: {https: 9443}, tls: {certificate: tests/fixtures/tls/example.com.pem, key: tests/fixtures/tls/example.com.key}}
+servers:
+  - {template: a, host: a.example.com, backend: /srv}
+  - {template: c, host: c.example.com, backend: /srv}
+");
+        validate(&cfg).unwrap();
+        let catch_all = |name: &str| cfg.catch_all(&cfg.templates[name]).map(|c| c.action.clone());
+        assert_eq!(catch_all("b"), Some(ConfigCatchAllAction::Close));
+        assert_eq!(catch_all("c"), Some(ConfigCatchAllAction::Page { status: 404, body: String::new() }));
+        assert_eq!(cfg.used_templates().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), vec!["a", "c"]);
+        assert!(cfg.uses_self_signed());
+    }
+
+    #[test]
+    fn validate_proxy_targets() {
+        for t in &["localhost:80", "10.0.0.1:3000", "[::1]:80", "unix:/run/a.sock", "http://backend", "https://a.b:8443/x/"] {
+            assert!(is_valid_proxy_target(t), "{}", t);
+        }
+        for t in &["localhost", "10.0.0.1:0", "a:b", "ftp://x", "unix:rel", "http://", "[::1]"] {
+            assert!(!is_valid_proxy_target(t), "{}", t);
+        }
+    }
+
+    #[test]
+    fn validate_upstreams() {
+        for t in &["30", "10s", "500ms", "1h30m", "2d"] {
+            assert!(is_nginx_time(t), "{}", t);
+        }
+        for t in &["", "s", "10x", "1.5s", "-1s"] {
+            assert!(!is_nginx_time(t), "{}", t);
+        }
+
+        let cfg = parse("
+templates:
+  web: {module: http, https: disabled, port: {}}
+servers:
+  - template: web
+    host: a.example.com
+    backend:
+      type: proxy
+      target:
+        - 10.0.0.1:8080
+        - backend.internal
+        - {server: 'unix:/run/app.sock', weight: 3, maxFails: 2, failTimeout: 30s, backup: true}
+  - template: web
+    host: b.example.com
+    backend:
+      type: proxy
+      target:
+        - {server: 'http://10.0.0.1', weight: 0, failTimeout: soon}
+        - {server: 10.0.0.2, backup: true}
+      balance: {hash: {key: ''}}
+  - template: web
+    host: c.example.com
+    backend: {type: proxy, target: []}
+");
+        match &cfg.servers[0].backend {
+            ConfigBackend::Proxy { target, balance, options } => {
+                assert!(balance.is_round_robin());
+                assert_eq!(**options, ConfigProxyOptions::default());
+                assert!(target[0].is_plain() && !target[2].is_plain());
+                assert_eq!(target[2].weight, Some(3));
+                assert_eq!(target[2].fail_timeout.as_deref(), Some("30s"));
+            },
+            b => panic!("unexpected backend {:?}", b),
+        }
+        let diagnostics = diagnostics(&cfg);
+        let paths = diagnostic_paths(&diagnostics);
+        assert_eq!(paths, vec![
+            "servers[1].backend.target[0]",
+            "servers[1].backend.target[0].weight",
+            "servers[1].backend.target[0].failTimeout",
+            "servers[1].backend.target[1].backup",
+            "servers[1].backend.balance.hash.key",
+            "servers[2].backend.target",
+        ]);
+    }
+
+    #[test]
+    fn validate_proxy_options() {
+        for s in &["0", "512k", "10m", "1G"] {
+            assert!(is_nginx_size(s), "{}", s);
+        }
+        for s in &["", "m", "10mb", "1.5m", "-1"] {
+            assert!(!is_nginx_size(s), "{}", s);
+        }
+
+        let cfg = parse("
+templates:
+  web: {module: http, https: disabled, port: {}}
+servers:
+  - template: web
+    host: a.example.com
+    backend:
+      type: proxy
+      target: 127.0.0.1:8080
+      forwarded: [for, realIp]
+      requestHeaders: {Host: $host}
+      connectTimeout: 5s
+      buffering: false
+      maxBodySize: 10m
+      websocket: true
+  - template: web
+    host: b.example.com
+    backend:
+      type: proxy
+      target: 127.0.0.1:8080
+      requestHeaders: {Bad Name: x, Connection: close}
+      responseHeaders: {X-Quote: 'say \"hi\"'}
+      readTimeout: forever
+      maxBodySize: 10mb
+      websocket: true
+");
+        match &cfg.servers[0].backend {
+            ConfigBackend::Proxy { options, .. } => {
+                assert_eq!(options.forwarded, vec![ConfigForwardedHeader::For, ConfigForwardedHeader::RealIp]);
+                assert_eq!(options.request_headers["Host"], "$host");
+                assert_eq!(options.buffering, Some(false));
+                assert!(options.websocket);
+            },
+            b => panic!("unexpected backend {:?}", b),
+        }
+        let diagnostics = diagnostics(&cfg);
+        let paths = diagnostic_paths(&diagnostics);
+        assert_eq!(paths, vec![
+            "servers[1].backend.requestHeaders.Bad Name",
+            "servers[1].backend.requestHeaders.Connection",
+            "servers[1].backend.responseHeaders.X-Quote",
+            "servers[1].backend.readTimeout",
+            "servers[1].backend.maxBodySize",
+        ]);
+    }
+
+    #[test]
+    fn validate_fastcgi() {
+        for t in &["127.0.0.1:9000", "[::1]:9000", "php:9000", "unix:/run/php.sock"] {
+            assert!(is_socket_address(t), "{}", t);
+        }
+        for t in &["127.0.0.1", "unix:php.sock", "http://php:9000"] {
+            assert!(!is_socket_address(t), "{}", t);
+        }
+
+        let cfg = parse("
+templates:
+  web: {module: http, https: disabled, port: {}}
+servers:
+  - template: web
+    host: a.example.com
+    backend: {type: fastcgi, target: 'unix:/run/php.sock', root: /srv/a, frontController: /index.php}
+  - template: web
+    host: b.example.com
+    backend:
+      type: fastcgi
+      target: 127.0.0.1
+      root: srv/b
+      index: public/index.php
+      frontController: index.php
+      params: {APP-ENV: x, APP_DEBUG: 'a\"b'}
+");
+        match &cfg.servers[0].backend {
+            ConfigBackend::FastCgi { index, front_controller, .. } => {
+                assert_eq!(index, "index.php");
+                assert_eq!(front_controller.as_deref(), Some("/index.php"));
+            },
+            b => panic!("unexpected backend {:?}", b),
+        }
+        let diagnostics = diagnostics(&cfg);
+        let paths = diagnostic_paths(&diagnostics);
+        assert_eq!(paths, vec![
+            "servers[1].backend.target",
+            "servers[1].backend.root",
+            "servers[1].backend.index",
+            "servers[1].backend.frontController",
+            "servers[1].backend.params.APP-ENV",
+            "servers[1].backend.params.APP_DEBUG",
+        ]);
+    }
+
+    #[test]
+    fn validate_uwsgi_scgi_grpc() {
+        let cfg = parse("
+templates:
+  web: {module: http, https: disabled, port: {}}
+servers:
+  - template: web
+    host: a.example.com
+    backend: {type: uwsgi, target: 'suwsgi://10.0.0.1:3031', modifier1: 30}
+  - template: web
+    host: a.example.com
+    location: /rpc
+    backend: {type: grpc, target: 'grpcs://10.0.0.1:50051', errors: {502: 14}}
+  - template: web
+    host: b.example.com
+    backend: {type: uwsgi, target: 'http://10.0.0.1:3031', params: {a-b: c}}
+  - template: web
+    host: b.example.com
+    location: /scgi
+    backend: {type: scgi, target: 10.0.0.1}
+  - template: web
+    host: b.example.com
+    location: /rpc
+    backend: {type: grpc, target: 'grpc://', errors: {200: 1, 502: 17}}
+");
+        let diagnostics = diagnostics(&cfg);
+        let paths = diagnostic_paths(&diagnostics);
+        assert_eq!(paths, vec![
+            "servers[2].backend.target",
+            "servers[2].backend.params.a-b",
+            "servers[3].backend.target",
+            "servers[4].backend.target",
+            "servers[4].backend.errors.200",
+            "servers[4].backend.errors.502",
+        ]);
+    }
+
+    #[test]
+    fn validate_stream_templates() {
+        let cfg = parse("
+templates:
+  web: {module: http, https: compatible, port: {}}
+  db: {module: stream, port: 5432}
+  db2: {module: stream, port: [5432, 5433]}
+  dns: {module: stream, protocol: udp, port: 443, preread: true}
+  empty: {module: stream, port: []}
+  https: {module: stream, port: 443}
+  sni: {module: stream, port: 8443, preread: true}
+  sni2: {module: stream, port: 8443, preread: true}
+");
+        let diagnostics = diagnostics(&cfg);
+        assert_eq!(diagnostics, vec![
+            Diagnostic { path: "templates.dns.preread".to_owned(), message: "SNI names are only read from TCP connections".to_owned() },
+            Diagnostic { path: "templates.empty.port".to_owned(), message: "no port given".to_owned() },
+            Diagnostic { path: "templates.db2.port".to_owned(), message: "port 5432 is also used by stream template \"db\"".to_owned() },
+            Diagnostic { path: "templates.https.port".to_owned(), message: "port 443 is also used by http template \"web\"".to_owned() },
+        ]);
+    }
+
+    #[test]
+    fn validate_stream_servers() {
+        let cfg = parse("
+templates:
+  db: {module: stream, port: 5432}
+  sni: {module: stream, port: 443, preread: true}
+servers:
+  - template: db
+    backend: {type: proxy, target: [10.0.0.1:5432, {server: 10.0.0.2:5432, weight: 2}], balance: leastConn, connectTimeout: 5s}
+  - template: sni
+    host: ['*.example.com', example.org]
+    backend: {type: proxy, target: 10.0.0.1:443}
+  - template: db
+    host: example.com
+    location: /db
+    backend: {type: proxy, target: 'http://10.0.0.1:5432', websocket: true}
+  - template: db
+    backend: {type: proxy, target: [10.0.0.1, 10.0.0.2:5432], balance: ipHash}
+  - template: sni
+    backend: {type: proxy, target: 10.0.0.1:443, readTimeout: 1h}
+  - template: db
+    backend: /srv/db
+");
+        let diagnostics = diagnostics(&cfg);
+        let paths = diagnostic_paths(&diagnostics);
+        assert_eq!(paths, vec![
+            "servers[2].host",
+            "servers[2].location",
+            "servers[2].backend.target",
+            "servers[2].backend.websocket",
+            "servers[3].backend.target[0]",
+            "servers[3].backend.balance",
+            "servers[4].host",
+            "servers[4].backend.readTimeout",
+            "servers[5].backend.type",
+        ]);
+    }
+
+    #[test]
+    fn validate_passthrough() {
+        let cfg = parse("
+templates:
+  web: {module: http, https: enforcing, port: {}}
+  open: {module: http, https: compatible, port: {}}
+servers:
+  - template: web
+    host: [vault.example.com, '*.vault.example.com']
+    backend: {type: passthrough, target: 10.0.0.1:8443}
+  - template: web
+    host: mail.example.com
+    location: /imap
+    backend: {type: passthrough, target: [10.0.0.1:993, 'http://10.0.0.2:993', {server: 10.0.0.3, weight: 0}], balance: ipHash}
+  - template: open
+    host: open.example.com
+    backend: {type: passthrough, target: 10.0.0.1}
+    tls: {certificate: /a.pem, key: /a.key}
+");
+        let diagnostics = diagnostics(&cfg);
+        let paths = diagnostic_paths(&diagnostics);
+        assert_eq!(paths, vec![
+            "servers[1].backend.target[1]",
+            "servers[1].backend.target[2]",
+            "servers[1].backend.target[2].weight",
+            "servers[1].backend.balance",
+            "servers[1].location",
+            "servers[2].backend.target",
+            "servers[2].backend.type",
+            "servers[2].tls",
+        ]);
+    }
+
+    #[test]
+    fn validate_file_options() {
+        let cfg = parse("
+templates:
+  web: {module: http, https: disabled, port: {}}
+servers:
+  - template: web
+    host: a.example.com
+    location: /img/
+    backend: {type: file, path: /srv/images/, alias: true, expires: {png: 7d, svg: max}}
+  - template: web
+    host: a.example.com
+    backend: {type: file, path: /srv/app, index: index.html, fallback: /index.html, precompressed: [gzip]}
+  - template: web
+    host: b.example.com
+    location: /img/
+    backend: {type: file, path: /srv/images, alias: true}
+  - template: web
+    host: b.example.com
+    location: /docs
+    backend:
+      type: file
+      path: /srv/docs/
+      alias: true
+      index: ['a b']
+      autoindexFormat: json
+      fallback: /index.html
+      tryFiles: [$uri]
+      expires: {'.css': 1d, js: soon}
+");
+        match &cfg.servers[1].backend {
+            ConfigBackend::File { options, .. } => {
+                assert!(!options.alias);
+                assert_eq!(options.index, vec!["index.html".to_owned()]);
+                assert_eq!(options.precompressed, vec![ConfigCompression::Gzip]);
+            },
+            b => panic!("unexpected backend {:?}", b),
+        }
+        let diagnostics = diagnostics(&cfg);
+        let paths = diagnostic_paths(&diagnostics);
+        assert_eq!(paths, vec![
+            "servers[2].backend.path",
+            "servers[3].backend.path",
+            "servers[3].backend.index[0]",
+            "servers[3].backend.autoindexFormat",
+            "servers[3].backend.fallback",
+            "servers[3].backend.expires..css",
+            "servers[3].backend.expires.js",
+        ]);
+        assert_eq!(diagnostics[0].message, "alias \"/srv/images\" and location \"/img/\" must both end with '/' or neither");
+    }
+
+    #[test]
+    fn validate_rewrites() {
+        assert!(host_matches("*.example.com", "a.b.example.com"));
+        assert!(host_matches(".example.com", "example.com"));
+        assert!(host_matches("www.example.*", "www.example.org"));
+        assert!(!host_matches("example.com", "www.example.com"));
+
+        let cfg = parse("
+templates:
+  web: {module: http, https: enforcing, port: {}, tls: {certificate: tests/fixtures/tls/example.com.pem, key: tests/fixtures/tls/example.com.key}}
+  plain: {module: http, https: disabled, port: {http: [80, 8080]}}
+servers:
+  - template: web
+    host: [example.com, www.example.com]
+    backend: {type: rewrite, target: 'https://example.com/', preservePath: true}
+  - template: web
+    host: example.com
+    location: /new
+    backend: /srv/new
+  - template: web
+    host: example.com
+    location: /old
+    backend: {type: rewrite, target: /new, code: permanent, preservePath: true}
+  - template: plain
+    host: old.example.org
+    backend: {type: rewrite, target: 'http://old.example.org:8080/x', code: 308}
+  - template: plain
+    host: a.example.org
+    backend: {type: rewrite, target: 'https://a.example.org', code: 200, keepMethod: true}
+  - template: plain
+    host: b.example.org
+    backend: {type: rewrite, target: '/b$request_uri', code: 307, keepMethod: true}
+  - template: plain
+    host: c.example.org
+    backend: {type: rewrite, target: 'https://c.example.org/', internal: true, pattern: 'x\\\\y', preservePath: true}
+  - template: plain
+    host: d.example.org
+    backend: {type: rewrite, target: '/d/$1', pattern: '^/(.*)$'}
+");
+        let diagnostics = diagnostics(&cfg);
+        let paths = diagnostic_paths(&diagnostics);
+        assert_eq!(paths, vec![
+            "servers[0].backend.target",
+            "servers[3].backend.target",
+            "servers[4].backend.code",
+            "servers[5].backend.keepMethod",
+            "servers[5].backend.target",
+            "servers[6].backend.target",
+            "servers[6].backend.pattern",
+            "servers[6].backend.preservePath",
+        ]);
+        assert_eq!(diagnostics[0].message, "rewrites back to this server");
+    }
+
+    #[test]
+    fn validate_tls() {
+        let cfg = parse("
+templates:
+  web:
+    module: http
+    https: only
+    tls:
+      certificate: tests/fixtures/tls/example.com.pem
+      key: tests/fixtures/tls/example.com.key
+      hosts:
+        example.org:
+          certificate: tests/fixtures/tls/example.org.pem
+          key: tests/fixtures/tls/missing.key
+      protocols: [TLSv1.2, SSLv2]
+  plain:
+    module: http
+    https: disabled
+    tls:
+      certificate: tests/fixtures/tls/example.com.pem
+      key: tests/fixtures/tls/example.com.key
+servers:
+  - template: web
+    host: [example.com, www.example.com, a.b.example.com, example.org, example.net]
+    backend: /srv/a
+  - template: plain
+    host: example.net
+    backend: /srv/b
+  - template: web
+    host: example.net
+    backend: /srv/c
+    tls:
+      certificate: tests/fixtures/tls/missing.pem
+      key: tests/fixtures/tls/example.com.key
+      hosts:
+        other.example.com:
+          certificate: tests/fixtures/tls/example.com.pem
+          key: tests/fixtures/tls/example.com.key
+");
+        let diagnostics = diagnostics(&cfg);
+        let paths = diagnostic_paths(&diagnostics);
+        assert_eq!(paths, vec![
+            "templates.web.tls.hosts.example.org.key",
+            "templates.web.tls.protocols[1]",
+            "servers[0].host[2]",
+            "servers[0].host[4]",
+            "servers[2].tls.certificate",
+            "servers[2].tls.hosts.other.example.com",
+        ]);
+        assert_eq!(diagnostics[2].message, "\"a.b.example.com\" is not covered by certificate \"tests/fixtures/tls/example.com.pem\"");
+
+        let cfg = parse("
+templates:
+  web: {module: http, https: compatible}
+  plain: {module: http, https: disabled}
+servers:
+  - {template: web, host: example.com, backend: /srv/a}
+  - template: web
+    host: www.example.com
+    backend: /srv/b
+    tls: {certificate: tests/fixtures/tls/example.com.pem, key: tests/fixtures/tls/example.com.key}
+  - {template: plain, host: example.org, backend: /srv/c}
+");
+        assert_eq!(self::diagnostics(&cfg), vec![
+            Diagnostic { path: "servers[0].tls".to_owned(), message: "HTTPS needs tls or acme".to_owned() },
+        ]);
+    }
+
+    #[test]
+    fn validate_acme() {
+        let cfg = parse("
+templates:
+  web:
+    module: http
+    https: only
+    port:
+      http: 8080
+    acme:
+      directory: ftp://acme.example.com
+  off:
+    module: http
+    https: disabled
+    acme: {}
+servers:
+  - template: web
+    host: [example.com, '*.example.com']
+    backend: /srv/a
+  - template: web
+    host: '.example.org'
+    backend: /srv/b
+    tls:
+      certificate: tests/fixtures/tls/example.org.pem
+      key: tests/fixtures/tls/example.org.key
+");
+        let diagnostics = diagnostics(&cfg);
+        let paths = diagnostic_paths(&diagnostics);
+        // servers[1] brings its own certificate, so only its coverage is checked
+        assert_eq!(paths, vec![
+            "templates.off.acme",
+            "templates.web.acme.directory",
+            "templates.web.port.http",
+            "servers[0].host[1]",
+            "servers[1].host[0]",
+        ]);
+        assert_eq!(diagnostics[4].message, "\".example.org\" is not covered by certificate \"tests/fixtures/tls/example.org.pem\"");
+
+        let server = &cfg.servers[0];
+        assert_eq!(server.acme(&cfg).unwrap().state, PathBuf::from("/var/lib/awsl/acme"));
+        assert_eq!(server.acme(&cfg).unwrap().certificate_path("example.com"), PathBuf::from("/var/lib/awsl/acme/example.com/fullchain.pem"));
+        // nothing is issued yet
+        assert_eq!(server.host_tls(&cfg, "example.com").unwrap().certificate, PathBuf::from("/var/lib/awsl/fallback/fallback.pem"));
+        assert!(cfg.uses_self_signed());
+        assert!(cfg.servers[1].acme(&cfg).is_none());
+    }
 }
@@ -0,0 +1,173 @@
+use std::fmt;
+use std::io;
+use super::config::Diagnostic;
+use super::interface::ServerInterface;
+use super::stream::StreamInterface;
+
+/// Where two descriptors collided in the registry, and which ones they were.
+#[derive(Debug)]
+pub struct Conflict {
+    pub host: Vec<String>,
+    pub interface: Vec<ServerInterface>,
+    pub location: Option<String>,
+    /// `get_key()` and source of the descriptor already registered.
+    pub existing: String,
+    pub existing_source: Option<String>,
+    /// `get_key()` and source of the descriptor being added.
+    pub incoming: String,
+    pub incoming_source: Option<String>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(host={:?}, interface={:?}, location={}): {}{} conflicts with {}{}",
+            self.host, self.interface, self.location.as_deref().unwrap_or("<root>"),
+            self.incoming, from(&self.incoming_source), self.existing, from(&self.existing_source))
+    }
+}
+
+/// Where two stream descriptors collided in the `StreamRegistry`.
+#[derive(Debug)]
+pub struct StreamConflict {
+    pub interface: StreamInterface,
+    /// The SNI name, or `None` for the listener's default route.
+    pub name: Option<String>,
+    pub existing: String,
+    pub existing_source: Option<String>,
+    pub incoming: String,
+    pub incoming_source: Option<String>,
+}
+
+impl fmt::Display for StreamConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(interface={:?}, name={}): {}{} conflicts with {}{}",
+            self.interface, self.name.as_deref().unwrap_or("<default>"),
+            self.incoming, from(&self.incoming_source), self.existing, from(&self.existing_source))
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// `WebServerInstance.host` has no entry.
+    EmptyHost,
+    /// `WebServerInstance.interface` has no entry.
+    EmptyInterface,
+    /// `OverwritePolicy::Error` refused to replace an existing descriptor.
+    OverwriteConflict(Box<Conflict>),
+    /// `OverwritePolicy::Error` refused a passthrough over a host that has
+    /// routes already, one conflict for each route it would displace.
+    PassthroughConflict(Vec<Conflict>),
+    /// `remove_server`/`replace_server` found nothing registered here.
+    RouteNotFound {
+        host: String,
+        interface: Box<ServerInterface>,
+        location: Option<String>,
+    },
+    /// Both the root server and a `/` subserver exist in one `WebServer`.
+    DuplicateLocation {
+        host: Vec<String>,
+        location: String,
+    },
+    /// The HTTPS interfaces of one `WebServer` carry different TLS settings.
+    TlsMismatch {
+        host: Vec<String>,
+    },
+    /// Two stream servers claim the same listener or SNI name.
+    StreamConflict(Box<StreamConflict>),
+    /// Stream servers on one listener disagree on TLS termination or preread.
+    StreamMismatch {
+        interface: StreamInterface,
+    },
+    UnknownTemplate {
+        template: String,
+    },
+    InvalidBackend {
+        key: String,
+        message: String,
+    },
+    InvalidTls {
+        key: String,
+        message: String,
+    },
+    /// Wraps an error raised while handling one entry of `Config.servers`.
+    Server {
+        index: usize,
+        name: Option<String>,
+        error: Box<Error>,
+    },
+    /// Wraps an error raised while adding the catch-all server of a template.
+    CatchAll {
+        template: String,
+        error: Box<Error>,
+    },
+    Validation(Vec<Diagnostic>),
+    /// The ACME server refused or failed a request.
+    Acme(String),
+    Io {
+        path: String,
+        error: io::Error,
+    },
+    Yaml(serde_yaml::Error),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+fn from(source: &Option<String>) -> String {
+    match source {
+        Some(s) => format!(" (from {})", s),
+        None => String::new(),
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::EmptyHost => write!(f, "host is empty list"),
+            Error::EmptyInterface => write!(f, "interface is empty list"),
+            Error::OverwriteConflict(c) => write!(f, "Cannot overwrite existed server {}", c),
+            Error::PassthroughConflict(conflicts) => {
+                write!(f, "Cannot pass through existed servers ")?;
+                for (i, c) in conflicts.iter().enumerate() {
+                    write!(f, "{}{}", if i == 0 { "" } else { "; " }, c)?;
+                }
+                Ok(())
+            },
+            Error::RouteNotFound { host, interface, location } => write!(f,
+                "no server registered for host={:?}, interface={:?}, location={}",
+                host, interface, location.as_deref().unwrap_or("<root>")),
+            Error::DuplicateLocation { host, location } =>
+                write!(f, "location {} is defined twice for {:?}", location, host),
+            Error::TlsMismatch { host } =>
+                write!(f, "HTTPS interfaces of {:?} use different TLS settings", host),
+            Error::StreamConflict(c) => write!(f, "Cannot overwrite existed stream server {}", c),
+            Error::StreamMismatch { interface } =>
+                write!(f, "stream servers on {:?} disagree on TLS termination or preread", interface),
+            Error::UnknownTemplate { template } => write!(f, "unknown template {:?}", template),
+            Error::InvalidBackend { key, message } => write!(f, "invalid backend {}: {}", key, message),
+            Error::InvalidTls { key, message } => write!(f, "invalid TLS settings {}: {}", key, message),
+            Error::Server { index, name: Some(name), error } => write!(f, "servers[{}] ({}): {}", index, name, error),
+            Error::Server { index, name: None, error } => write!(f, "servers[{}]: {}", index, error),
+            Error::CatchAll { template, error } => write!(f, "catch-all of templates.{}: {}", template, error),
+            Error::Validation(diagnostics) => {
+                write!(f, "{} configuration error(s)", diagnostics.len())?;
+                for d in diagnostics {
+                    write!(f, "\n  {}", d)?;
+                }
+                Ok(())
+            },
+            Error::Acme(message) => write!(f, "ACME: {}", message),
+            Error::Io { path, error } => write!(f, "{}: {}", path, error),
+            Error::Yaml(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+// Display already spells out the wrapped errors, as Validation does its
+// diagnostics, so none is handed out again as a source.
+impl std::error::Error for Error {}
+
+impl From<serde_yaml::Error> for Error {
+    fn from(error: serde_yaml::Error) -> Self {
+        Error::Yaml(error)
+    }
+}
@@ -25,17 +25,64 @@ id1:
 
 */
 
-pub use std::error::Error as Error;
+pub use super::error::{Conflict, Error, Result};
+use super::stream::StreamDescriptor;
 
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum OverwritePolicy {
     Error,
     Ignore,
     Overwrite,
+    Warn, // overwrite, but flag the record as a warning
+}
+
+/// A decision `add_server` made about an already registered descriptor.
+#[derive(Debug)]
+pub struct OverwriteRecord {
+    pub policy: OverwritePolicy,
+    pub conflict: Conflict,
+}
+
+impl OverwriteRecord {
+    pub fn is_warning(&self) -> bool {
+        self.policy == OverwritePolicy::Warn
+    }
+}
+
+impl std::fmt::Display for OverwriteRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let action = match self.policy {
+            OverwritePolicy::Ignore => "ignored",
+            _ => "overwrote",
+        };
+        write!(f, "{} at {}", action, self.conflict)
+    }
 }
 
-pub trait BackendDescriptor: std::fmt::Debug {
+pub trait BackendDescriptor: std::fmt::Debug + Send + Sync {
     fn get_key(&self) -> String; // should be unique
-    fn to_backend_config(&self) -> Result<String, Box<dyn Error>>;
+    fn to_backend_config(&self) -> Result<String>;
+    /// Blocks needed at `http {}` level, such as upstreams; the renderer
+    /// emits each `get_key()` once however many routes ask for it.
+    fn get_http_blocks(&self) -> Vec<Arc<dyn BackendDescriptor>> {
+        Vec::new()
+    }
+    /// Blocks needed at `server {}` level, such as named locations; emitted
+    /// once per server block.
+    fn get_server_blocks(&self) -> Vec<Arc<dyn BackendDescriptor>> {
+        Vec::new()
+    }
+    /// Whether the interfaces serving this descriptor must speak HTTP/2,
+    /// as gRPC does.
+    fn requires_http2(&self) -> bool {
+        false
+    }
+    /// The stream backend that takes whole TLS connections of this route's
+    /// hosts by SNI name, if any; such a route owns its host on each of its
+    /// interfaces, leaving no room for locations next to it.
+    fn as_passthrough(&self) -> Option<Arc<dyn StreamDescriptor>> {
+        None
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -43,15 +90,181 @@ pub enum ServerInterfaceAttribute {
     Http, Https
 }
 
-#[derive(Copy, Clone, PartialEq)]
+/// Certificate and `ssl_*` settings shared by the HTTPS interfaces of a server.
+pub trait TlsDescriptor: std::fmt::Debug + Send + Sync {
+    fn get_key(&self) -> String; // should be unique
+    fn to_tls_config(&self) -> Result<String>;
+    /// The certificate file served, for inspection.
+    fn get_certificate(&self) -> Option<&std::path::Path> {
+        None
+    }
+}
+
+/// `listen` parameters an interface asks for.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ListenOptions {
+    pub http2: bool,
+    /// A `quic` listener next to the TLS one, advertised by `Alt-Svc`.
+    pub http3: bool,
+    /// The addresses trusted to send the PROXY protocol header, whose
+    /// client addresses replace theirs; `None` leaves the protocol off.
+    pub proxy_protocol: Option<Vec<String>>,
+    pub default_server: bool,
+    pub reuseport: bool,
+}
+
+impl ListenOptions {
+    fn merge(&mut self, other: &ListenOptions) {
+        self.http2 |= other.http2;
+        self.http3 |= other.http3;
+        self.default_server |= other.default_server;
+        self.reuseport |= other.reuseport;
+        if let Some(ranges) = &other.proxy_protocol {
+            let merged = self.proxy_protocol.get_or_insert_with(Vec::new);
+            for range in ranges {
+                if !merged.contains(range) {
+                    merged.push(range.clone());
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct ServerInterface {
+    /// An IPv4 address, an IPv6 address in brackets or `unix:/path`;
+    /// `None` listens on every address. Unix sockets have no port.
+    address: Option<String>,
     port: u16,
     attr: ServerInterfaceAttribute,
+    tls: Option<Arc<dyn TlsDescriptor>>,
+    // not part of the interface's identity: equal interfaces of one host
+    // share their options
+    options: ListenOptions,
+    // maintained by the registry: nginx enables HTTP/2 and the PROXY
+    // protocol per socket
+    http2: bool,
+    proxy_protocol: Option<Vec<String>>,
+}
+
+impl ServerInterface {
+    pub fn new(port: u16, attr: ServerInterfaceAttribute) -> Self {
+        ServerInterface { address: None, port, attr, tls: None, options: Default::default(), http2: false, proxy_protocol: None }
+    }
+
+    /// A unix socket at `path` (without the `unix:` prefix).
+    pub fn unix(path: &str, attr: ServerInterfaceAttribute) -> Self {
+        ServerInterface { address: Some(format!("unix:{}", path)), port: 0, attr, tls: None, options: Default::default(), http2: false, proxy_protocol: None }
+    }
+
+    pub fn with_address(mut self, address: String) -> Self {
+        self.address = Some(address);
+        self
+    }
+
+    pub fn with_tls(mut self, tls: Arc<dyn TlsDescriptor>) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    pub fn with_options(mut self, options: ListenOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn get_address(&self) -> Option<&str> {
+        self.address.as_deref()
+    }
+
+    pub fn get_port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn is_unix(&self) -> bool {
+        self.address.as_deref().is_some_and(|a| a.starts_with("unix:"))
+    }
+
+    /// What nginx's `listen` takes: `port`, `address:port` or `unix:/path`.
+    pub fn get_listen(&self) -> String {
+        match &self.address {
+            Some(address) if self.is_unix() => address.clone(),
+            Some(address) => format!("{}:{}", address, self.port),
+            None => self.port.to_string(),
+        }
+    }
+
+    pub fn get_attr(&self) -> ServerInterfaceAttribute {
+        self.attr
+    }
+
+    pub fn get_tls(&self) -> Option<&Arc<dyn TlsDescriptor>> {
+        self.tls.as_ref()
+    }
+
+    /// The options asked for; see `is_http2` and `get_proxy_protocol` for
+    /// those in effect.
+    pub fn get_options(&self) -> &ListenOptions {
+        &self.options
+    }
+
+    /// Whether HTTP/2 is on for this socket, because an interface on it
+    /// asks for it or a route on it requires it.
+    pub fn is_http2(&self) -> bool {
+        self.http2
+    }
+
+    /// The trusted addresses of every interface on this socket that takes
+    /// the PROXY protocol, if any does.
+    pub fn get_proxy_protocol(&self) -> Option<&Vec<String>> {
+        self.proxy_protocol.as_ref()
+    }
+
+    /// What shares socket options: one address:port, or one unix socket.
+    fn socket(&self) -> String {
+        socket_key(&self.get_listen())
+    }
+
+    fn tls_key(&self) -> Option<String> {
+        self.tls.as_ref().map(|t| t.get_key())
+    }
+}
+
+/// The socket nginx binds for a `listen` value: the IPv4 wildcard, however
+/// it is spelled, is the bare port and IPv6 addresses take their canonical form.
+pub fn socket_key(listen: &str) -> String {
+    let (address, port) = match listen.rsplit_once(':') {
+        Some(split) if !listen.starts_with("unix:") => split,
+        _ => return listen.to_owned(),
+    };
+    match address.strip_prefix('[').and_then(|a| a.strip_suffix(']')).map(str::parse::<std::net::Ipv6Addr>) {
+        Some(Ok(v6)) => format!("[{}]:{}", v6, port),
+        _ if address == "*" || address == "0.0.0.0" => port.to_owned(),
+        _ => listen.to_owned(),
+    }
+}
+
+impl PartialEq for ServerInterface {
+    fn eq(&self, other: &Self) -> bool {
+        self.address == other.address && self.port == other.port && self.attr == other.attr && self.tls_key() == other.tls_key()
+    }
 }
 
 impl std::fmt::Debug for ServerInterface {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_fmt(format_args!("{:?}:{}", self.attr, self.port))
+        f.write_fmt(format_args!("{:?}:{}", self.attr, self.get_listen()))?;
+        if let Some(tls) = &self.tls {
+            f.write_fmt(format_args!("[{}]", tls.get_key()))?;
+        }
+        if self.http2 {
+            f.write_str("+h2")?;
+        }
+        if self.options.http3 {
+            f.write_str("+h3")?;
+        }
+        if self.proxy_protocol.is_some() {
+            f.write_str("+pp")?;
+        }
+        Ok(())
     }
 }
 
@@ -61,6 +274,42 @@ pub struct WebServerInstance {
     interface: Vec<ServerInterface>,
     location: Option<String>,
     descriptor: Arc<dyn BackendDescriptor>,
+    source: Option<String>, // where the instance came from, for diagnostics
+}
+
+impl WebServerInstance {
+    pub fn new(host: Vec<String>, interface: Vec<ServerInterface>, location: Option<String>, descriptor: Arc<dyn BackendDescriptor>) -> Self {
+        WebServerInstance { host, interface, location, descriptor, source: None }
+    }
+
+    pub fn with_source(mut self, source: String) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    fn to_route(&self) -> Route {
+        Route {
+            descriptor: self.descriptor.clone(),
+            source: self.source.clone(),
+        }
+    }
+}
+
+/// A descriptor registered in a `WebServer`, together with its origin.
+#[derive(Clone)]
+pub struct Route {
+    descriptor: Arc<dyn BackendDescriptor>,
+    source: Option<String>,
+}
+
+impl Route {
+    pub fn get_descriptor(&self) -> &Arc<dyn BackendDescriptor> {
+        &self.descriptor
+    }
+
+    pub fn get_source(&self) -> Option<&String> {
+        self.source.as_ref()
+    }
 }
 
 #[derive(Clone)]
@@ -68,8 +317,85 @@ pub struct WebServer {
     host: Vec<String>,
     interface: Vec<ServerInterface>,
 
-    subservers: BTreeMap<String, Arc<dyn BackendDescriptor>>,
-    server: Option<Arc<dyn BackendDescriptor>>,
+    subservers: BTreeMap<String, Route>,
+    server: Option<Route>,
+}
+
+impl WebServer {
+    pub fn get_host(&self) -> &Vec<String> {
+        &self.host
+    }
+
+    pub fn get_interface(&self) -> &Vec<ServerInterface> {
+        &self.interface
+    }
+
+    pub fn get_subservers(&self) -> &BTreeMap<String, Route> {
+        &self.subservers
+    }
+
+    pub fn get_server(&self) -> Option<&Route> {
+        self.server.as_ref()
+    }
+
+    /// The stream backend taking the whole TLS connections of this server.
+    pub fn get_passthrough(&self) -> Option<Arc<dyn StreamDescriptor>> {
+        self.server.as_ref().and_then(|r| r.descriptor.as_passthrough())
+    }
+
+    fn routes(&self) -> impl Iterator<Item = &Route> {
+        self.server.iter().chain(self.subservers.values())
+    }
+
+    /// The TLS settings of the HTTPS interfaces, which nginx can only take
+    /// once per server block.
+    pub fn get_tls(&self) -> Result<Option<&Arc<dyn TlsDescriptor>>> {
+        let mut https = self.interface.iter().filter(|i| i.attr == ServerInterfaceAttribute::Https);
+        let first = match https.next() {
+            Some(i) => i,
+            None => return Ok(None),
+        };
+        if https.any(|i| i.tls_key() != first.tls_key()) {
+            return Err(Error::TlsMismatch { host: self.host.clone() });
+        }
+        Ok(first.get_tls())
+    }
+}
+
+impl WebServer {
+    fn is_empty(&self) -> bool {
+        self.server.is_none() && self.subservers.is_empty()
+    }
+
+    fn route_mut(&mut self, location: Option<&str>) -> Option<&mut Route> {
+        match location {
+            Some(loc) => self.subservers.get_mut(loc),
+            None => self.server.as_mut(),
+        }
+    }
+
+    /// Same descriptors (compared by `get_key()`) on the same locations.
+    fn same_routes(&self, other: &WebServer) -> bool {
+        let key = |r: &Route| r.descriptor.get_key();
+        self.server.as_ref().map(key) == other.server.as_ref().map(key)
+            && self.subservers.len() == other.subservers.len()
+            && self.subservers.iter().zip(other.subservers.iter())
+                .all(|((la, ra), (lb, rb))| la == lb && key(ra) == key(rb))
+    }
+}
+
+/// Whether joining the interfaces of `a` and `b` keeps a single TLS setting.
+fn same_https_tls(a: &WebServer, b: &WebServer) -> bool {
+    let tls = |w: &WebServer| w.interface.iter()
+        .filter(|i| i.attr == ServerInterfaceAttribute::Https)
+        .map(|i| i.tls_key())
+        .collect::<Vec<_>>();
+    let (ta, tb) = (tls(a), tls(b));
+    ta.iter().chain(tb.iter()).all(|k| Some(k) == ta.first().or_else(|| tb.first()))
+}
+
+fn same_set<T: PartialEq>(a: &[T], b: &[T]) -> bool {
+    a.len() == b.len() && a.iter().all(|x| b.contains(x))
 }
 
 impl std::fmt::Debug for WebServer {
@@ -80,22 +406,20 @@ impl std::fmt::Debug for WebServer {
 
 
 pub trait WebRegistry {
-    fn add_server(&mut self, inst: &WebServerInstance, policy: OverwritePolicy) -> Result<&mut Self, Box<dyn Error>>;
+    fn add_server(&mut self, inst: &WebServerInstance, policy: OverwritePolicy) -> Result<&mut Self>;
+    fn remove_server(&mut self, host: &[String], interface: &[ServerInterface], location: Option<&str>) -> Result<&mut Self>;
+    fn replace_server(&mut self, inst: &WebServerInstance) -> Result<&mut Self>;
+    fn normalize(&mut self) -> &mut Self;
     fn clear(&mut self);
 
     fn get_web_servers(&self) -> &Vec<WebServer>;
+    fn get_overwrite_log(&self) -> &Vec<OverwriteRecord>;
 }
 
+#[derive(Default)]
 pub struct Registry {
     web: Vec<WebServer>,
-}
-
-impl std::default::Default for Registry {
-    fn default() -> Self {
-        Registry {
-            web: Vec::new(),
-        }
-    }
+    log: Vec<OverwriteRecord>,
 }
 
 // impl Registry {
@@ -124,28 +448,186 @@ macro_rules! test_println {
     };
 }
 
-macro_rules! execute_overwrite_policy {
-    ($policy: expr, $check: expr, $w: stmt) => {
-        execute_overwrite_policy!($policy, $check, $w,
-            Box::new(std::io::Error::new(std::io::ErrorKind::AlreadyExists, "Cannot override existed value"))
-        )
-    };
-    ($policy: expr, $check: expr, $w: stmt, $err: literal) => {
-        execute_overwrite_policy!($policy, $check, $w,
-            Box::new(std::io::Error::new(std::io::ErrorKind::AlreadyExists, $err))
-        )
-    };
+impl Registry {
+    /// Turns HTTP/2 on for every interface on an address:port where some
+    /// interface asks for it or some route requires it, and off everywhere
+    /// else; the PROXY protocol likewise, trusting the addresses of all of them.
+    fn update_sockets(&mut self) {
+        let mut sockets: Vec<(String, ListenOptions)> = Vec::new();
+        for web in &self.web {
+            let requires_http2 = web.routes().any(|r| r.descriptor.requires_http2());
+            for interface in &web.interface {
+                let mut options = interface.options.clone();
+                options.http2 |= requires_http2;
+                match sockets.iter_mut().find(|(s, _)| *s == interface.socket()) {
+                    Some((_, o)) => o.merge(&options),
+                    None => sockets.push((interface.socket(), options)),
+                }
+            }
+        }
+        for web in &mut self.web {
+            for interface in &mut web.interface {
+                let (_, options) = sockets.iter().find(|(s, _)| *s == interface.socket()).unwrap();
+                interface.http2 = options.http2;
+                interface.proxy_protocol = options.proxy_protocol.clone();
+            }
+        }
+    }
+
+    /// The HTTPS `listen`s where some host is passed through, so the servers
+    /// terminating TLS there move behind the stream listener.
+    pub fn get_passthrough_listens(&self) -> Vec<String> {
+        let mut listens: Vec<String> = self.web.iter()
+            .filter(|w| w.get_passthrough().is_some())
+            .flat_map(|w| w.interface.iter().filter(|i| i.attr == ServerInterfaceAttribute::Https && !i.is_unix()).map(|i| i.get_listen()))
+            .collect();
+        listens.sort_unstable();
+        listens.dedup();
+        listens
+    }
+
+    fn find_web(&self, host: &str, interface: &ServerInterface) -> Option<usize> {
+        self.web.iter().position(|w| w.host.iter().any(|h| h == host) && w.interface.contains(interface))
+    }
+
+    /// Splits the `WebServer` at `idx` so that it serves exactly
+    /// (`host`, `interface`); the rest is appended as new entries.
+    fn isolate(&mut self, idx: usize, host: &str, interface: &ServerInterface) -> Vec<usize> {
+        let mut touched = vec![idx];
+        let web = &mut self.web[idx];
+        let mut rest = Vec::new();
+        if web.host.len() > 1 {
+            let mut other = web.clone();
+            other.host.retain(|h| h != host);
+            web.host.retain(|h| h == host);
+            rest.push(other);
+        }
+        if web.interface.len() > 1 {
+            let mut other = web.clone();
+            other.interface.retain(|i| i != interface);
+            web.interface.retain(|i| i == interface);
+            rest.push(other);
+        }
+        for other in rest {
+            touched.push(self.web.len());
+            self.web.push(other);
+        }
+        touched
+    }
+
+    fn check_routes(&self, host: &[String], interface: &[ServerInterface], location: Option<&str>) -> Result<()> {
+        for h in host {
+            for i in interface {
+                let found = self.find_web(h, i).map(|idx| match location {
+                    Some(loc) => self.web[idx].subservers.contains_key(loc),
+                    None => self.web[idx].server.is_some(),
+                });
+                if found != Some(true) {
+                    return Err(Error::RouteNotFound {
+                        host: h.clone(),
+                        interface: Box::new(i.clone()),
+                        location: location.map(|x| x.to_owned()),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops emptied entries, then joins every dirty `WebServer` with any
+    /// equivalent one that shares its host set or its interface set.
+    fn merge_equivalent(&mut self, mut dirty: Vec<bool>) {
+        let mut idx = 0;
+        while idx < self.web.len() {
+            if self.web[idx].is_empty() {
+                self.web.remove(idx);
+                dirty.remove(idx);
+            } else {
+                idx += 1;
+            }
+        }
+
+        'merge: loop {
+            for a in 0..self.web.len() {
+                for b in (a + 1)..self.web.len() {
+                    if !(dirty[a] || dirty[b]) || !self.web[a].same_routes(&self.web[b]) {
+                        continue;
+                    }
+                    let (wa, wb) = (&self.web[a], &self.web[b]);
+                    if same_set(&wa.interface, &wb.interface) {
+                        let (hosts, interfaces) = (wb.host.clone(), wb.interface.clone());
+                        self.web[a].host.extend(hosts);
+                        merge_options(&mut self.web[a].interface, &interfaces);
+                    } else if same_set(&wa.host, &wb.host) && same_https_tls(wa, wb) {
+                        let interfaces = wb.interface.clone();
+                        self.web[a].interface.extend(interfaces);
+                    } else {
+                        continue;
+                    }
+                    test_println!("Merged {:?}", self.web[a]);
+                    self.web.remove(b);
+                    dirty.remove(b);
+                    dirty[a] = true;
+                    continue 'merge;
+                }
+            }
+            break;
+        }
+    }
 
-    ($policy: expr, $check: expr, $w: stmt, $err: expr) => {
+    fn modify_routes<F>(&mut self, host: &[String], interface: &[ServerInterface], f: F)
+    where
+        F: Fn(&mut WebServer),
+    {
+        let mut dirty = vec![false; self.web.len()];
+        for h in host {
+            for i in interface {
+                // check_routes guarantees the pair exists
+                let idx = self.find_web(h, i).unwrap();
+                for t in self.isolate(idx, h, i) {
+                    dirty.resize(self.web.len(), false);
+                    dirty[t] = true;
+                }
+                f(&mut self.web[idx]);
+            }
+        }
+        self.merge_equivalent(dirty);
+    }
+}
+
+/// Adds the options of `incoming` to the equal interfaces of `interface`.
+fn merge_options(interface: &mut [ServerInterface], incoming: &[ServerInterface]) {
+    for existing in interface {
+        if let Some(i) = incoming.iter().find(|i| *i == existing) {
+            existing.options.merge(&i.options);
+        }
+    }
+}
+
+fn overwrite_conflict(web: &WebServer, location: Option<&String>, existing: &Route, inst: &WebServerInstance) -> Conflict {
+    Conflict {
+        host: web.host.clone(),
+        interface: web.interface.clone(),
+        location: location.cloned(),
+        existing: existing.descriptor.get_key(),
+        existing_source: existing.source.clone(),
+        incoming: inst.descriptor.get_key(),
+        incoming_source: inst.source.clone(),
+    }
+}
+
+macro_rules! execute_overwrite_policy {
+    ($policy: expr, $log: expr, $check: expr, $w: stmt, $conflict: expr) => {
         if $check {
             match $policy {
                 OverwritePolicy::Error => {
-                    return Err($err);
+                    return Err(Error::OverwriteConflict(Box::new($conflict)));
                 },
                 OverwritePolicy::Ignore => {
-                    // do nothing
+                    $log.push(OverwriteRecord { policy: $policy, conflict: $conflict });
                 },
-                OverwritePolicy::Overwrite => {
+                OverwritePolicy::Overwrite | OverwritePolicy::Warn => {
+                    $log.push(OverwriteRecord { policy: $policy, conflict: $conflict });
                     $w
                 },
             }
@@ -156,19 +638,19 @@ macro_rules! execute_overwrite_policy {
 }
 
 impl WebRegistry for Registry {
-    fn add_server(&mut self, inst: &WebServerInstance, policy: OverwritePolicy) -> Result<&mut Self, Box<dyn Error>> {
+    fn add_server(&mut self, inst: &WebServerInstance, policy: OverwritePolicy) -> Result<&mut Self> {
         test_println!("Add server");
 
-        if inst.host.len() == 0 {
-            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidData, "host is empty list")));
+        if inst.host.is_empty() {
+            return Err(Error::EmptyHost);
         }
-        if inst.interface.len() == 0 {
-            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidData, "interface is empty list")));
+        if inst.interface.is_empty() {
+            return Err(Error::EmptyInterface);
         }
         let mut pairs = Vec::new();
         pairs.push((inst.host.clone(), inst.interface.clone()));
 
-        while pairs.len() > 0 {
+        while !pairs.is_empty() {
             let (hosts, interfaces_for_all_hosts) = &mut pairs[0];
             let mut new_pairs = Vec::new();
 
@@ -195,8 +677,8 @@ impl WebRegistry for Registry {
                         let mut other_interfaces: Vec<ServerInterface> = Vec::new();
                         for h in &web_host.host {
                             let mut known_id = None;
-                            'findhost: for id in 0..hosts.len() {
-                                if h == &hosts[id] {
+                            'findhost: for (id, known) in hosts.iter().enumerate() {
+                                if h == known {
                                     known_hosts.push(h.clone());
                                     known_id = Some(id);
                                     break 'findhost;
@@ -208,8 +690,7 @@ impl WebRegistry for Registry {
                                 other_hosts.push(h.clone());
                             }
                         }
-                        for i in 0..interfaces.len() {
-                            let v = &interfaces[i];
+                        for v in &interfaces {
                             if web_host.interface.contains(v) {
                                 known_interfaces.push(v.clone());
                             } else {
@@ -217,26 +698,28 @@ impl WebRegistry for Registry {
                             }
                         }
                         for v in &web_host.interface {
-                            if !known_interfaces.contains(&v) {
+                            if !known_interfaces.contains(v) {
                                 other_interfaces.push(v.clone());
                             }
                         }
-                        test_println!("KH {:?}", &known_hosts);
-                        test_println!("UH {:?}", &hosts);
-                        test_println!("OH {:?}", &other_hosts);
-                        test_println!("KI {:?}", &known_interfaces);
-                        test_println!("UI {:?}", &unknown_interfaces);
-                        test_println!("OI {:?}", &other_interfaces);
-
-                        if known_hosts.len() == 0 || known_interfaces.len() == 0 {
+                        test_println!("KH {:?}", known_hosts);
+                        test_println!("UH {:?}", hosts);
+                        test_println!("OH {:?}", other_hosts);
+                        test_println!("KI {:?}", known_interfaces);
+                        test_println!("UI {:?}", unknown_interfaces);
+                        test_println!("OI {:?}", other_interfaces);
+
+                        if known_hosts.is_empty() || known_interfaces.is_empty() {
                             test_println!("Not current node, skipping");
                             hosts.extend(known_hosts);  // restore hosts in pair
                             test_println!("Current pair: ({:?}, {:?})", hosts, interfaces_for_all_hosts);
                             continue 'host_search_loop;
                         }
 
+                        merge_options(&mut web_host.interface, &interfaces);
+
                         // logics to clear other_hosts (split web_host)
-                        if other_hosts.len() > 0 {
+                        if !other_hosts.is_empty() {
                             test_println!("Host split {:?} KH={:?}, OH={:?}", web_host, known_hosts, other_hosts);
                             let mut new_host = web_host.clone();
                             new_host.host = other_hosts;
@@ -250,7 +733,7 @@ impl WebRegistry for Registry {
                         // unknown hosts are left
 
                         // logics to clear other_interfaces (split web_host)
-                        if other_interfaces.len() > 0 {
+                        if !other_interfaces.is_empty() {
                             test_println!("Interface split {:?} KI={:?}, OI={:?}", web_host, known_interfaces, other_interfaces);
                             let mut new_host = web_host.clone();
                             new_host.interface = other_interfaces;
@@ -262,27 +745,52 @@ impl WebRegistry for Registry {
                         }
 
                         // logics to clear unknown_interfaces (leave)
-                        if unknown_interfaces.len() > 0 {
+                        if !unknown_interfaces.is_empty() {
                             interfaces.clear();
                             interfaces.extend(unknown_interfaces);
                         }
                         
                         // logics to clear known_interfaces
                         test_println!("Overwrite on {:?}", web_host);
-                        if let Some(loc) = &inst.location {
-                            execute_overwrite_policy!(policy, web_host.subservers.contains_key(loc), {
-                                web_host.subservers.insert(loc.clone(), inst.descriptor.clone());
-                            }, "Cannot overwrite existed server");
+                        let owner = web_host.server.as_ref().filter(|r| r.descriptor.as_passthrough().is_some()).cloned();
+                        if let (Some(loc), Some(owner)) = (&inst.location, owner) {
+                            // a passthrough owns the host on this interface, locations included
+                            execute_overwrite_policy!(policy, self.log, true, {
+                                web_host.server = None;
+                                web_host.subservers.insert(loc.clone(), inst.to_route());
+                            }, overwrite_conflict(web_host, Some(loc), &owner, inst));
+                        } else if inst.descriptor.as_passthrough().is_some() && !web_host.subservers.is_empty() {
+                            // the passthrough displaces the root and every location at once
+                            let conflicts: Vec<Conflict> = web_host.server.iter().map(|r| overwrite_conflict(web_host, None, r, inst))
+                                .chain(web_host.subservers.iter().map(|(loc, r)| overwrite_conflict(web_host, Some(loc), r, inst)))
+                                .collect();
+                            match policy {
+                                OverwritePolicy::Error => {
+                                    return Err(Error::PassthroughConflict(conflicts));
+                                },
+                                OverwritePolicy::Ignore => {
+                                    self.log.extend(conflicts.into_iter().map(|conflict| OverwriteRecord { policy, conflict }));
+                                },
+                                OverwritePolicy::Overwrite | OverwritePolicy::Warn => {
+                                    self.log.extend(conflicts.into_iter().map(|conflict| OverwriteRecord { policy, conflict }));
+                                    web_host.subservers.clear();
+                                    web_host.server = Some(inst.to_route());
+                                },
+                            }
+                        } else if let Some(loc) = &inst.location {
+                            execute_overwrite_policy!(policy, self.log, web_host.subservers.contains_key(loc), {
+                                web_host.subservers.insert(loc.clone(), inst.to_route());
+                            }, overwrite_conflict(web_host, Some(loc), &web_host.subservers[loc], inst));
                         } else {
-                            execute_overwrite_policy!(policy, web_host.server.is_some(), {
-                                web_host.server = Some(inst.descriptor.clone());
-                            }, "Cannot overwrite existed server");
+                            execute_overwrite_policy!(policy, self.log, web_host.server.is_some(), {
+                                web_host.server = Some(inst.to_route());
+                            }, overwrite_conflict(web_host, None, web_host.server.as_ref().unwrap(), inst));
                         }
                     }
                 }
                 self.web.extend(new_hosts);
 
-                if interfaces.len() > 0 && interfaces.len() != interfaces_for_all_hosts.len() {
+                if !interfaces.is_empty() && interfaces.len() != interfaces_for_all_hosts.len() {
                     test_println!("Add new pair ({:?}, {:?})", host, interfaces);
                     new_pairs.push((vec![host.clone()], interfaces));
                 } else {
@@ -293,7 +801,7 @@ impl WebRegistry for Registry {
                 test_println!("> Searched host {:?} self.web {:?}", host, self.web);
             }
 
-            if hosts.len() > 0 {
+            if !hosts.is_empty() {
                 test_println!("Creating host {:?} interface {:?}", hosts, interfaces_for_all_hosts);
                 let mut server = WebServer {
                     host: hosts.clone(),
@@ -302,9 +810,9 @@ impl WebRegistry for Registry {
                     server: None
                 };
                 if let Some(loc) = &inst.location {
-                    server.subservers.insert(loc.clone(), inst.descriptor.clone());
+                    server.subservers.insert(loc.clone(), inst.to_route());
                 } else {
-                    server.server = Some(inst.descriptor.clone());
+                    server.server = Some(inst.to_route());
                 }
                 self.web.push(server);
             }
@@ -313,47 +821,58 @@ impl WebRegistry for Registry {
             pairs.remove(0);
             test_println!(">> Pairs after processing {:?}", &pairs);
         }
+        self.update_sockets();
         test_println!(">>> Output {:?}", self.web);
         Ok(self)
     }
+    fn remove_server(&mut self, host: &[String], interface: &[ServerInterface], location: Option<&str>) -> Result<&mut Self> {
+        test_println!("Remove server {:?} {:?} {:?}", host, interface, location);
+        self.check_routes(host, interface, location)?;
+        self.modify_routes(host, interface, |web| match location {
+            Some(loc) => { web.subservers.remove(loc); },
+            None => web.server = None,
+        });
+        self.update_sockets();
+        Ok(self)
+    }
+
+    fn replace_server(&mut self, inst: &WebServerInstance) -> Result<&mut Self> {
+        test_println!("Replace server");
+        let location = inst.location.as_deref();
+        self.check_routes(&inst.host, &inst.interface, location)?;
+        let route = inst.to_route();
+        self.modify_routes(&inst.host, &inst.interface, |web| {
+            *web.route_mut(location).unwrap() = route.clone();
+        });
+        self.update_sockets();
+        Ok(self)
+    }
+
+    fn normalize(&mut self) -> &mut Self {
+        test_println!("Normalize");
+        let dirty = vec![true; self.web.len()];
+        self.merge_equivalent(dirty);
+        self
+    }
+
     fn clear(&mut self) {
         self.web.clear();
+        self.log.clear();
     }
 
     fn get_web_servers(&self) -> &Vec<WebServer> {
         &self.web
     }
-}
-
 
-/*
-impl NginxHttpConfig for Registry {
-    type Err = Void;
-    fn to_nginx_http_config() -> Result<String, Self::Err> {
-        Ok(String::from("http {\n\n") + self.to_nginx_server_blocks()? + "\n}\n")
-    }
-    fn to_nginx_server_blocks() -> Result<String, Self::Err> {
-        Ok("# Unimplemented!".to_owned())
+    fn get_overwrite_log(&self) -> &Vec<OverwriteRecord> {
+        &self.log
     }
 }
-*/
 
 #[cfg(test)]
 mod tests {
     use super::*;
-
-    #[derive(Debug)]
-    struct NullBackend {
-        key: String,
-    }
-    impl BackendDescriptor for NullBackend {
-        fn get_key(&self) -> String {
-            self.key.clone()
-        }
-        fn to_backend_config(&self) -> Result<String, Box<dyn Error>> {
-            Ok(format!("return 200 \"Hello world\"; # {:?}", self))
-        }
-    }
+    use super::super::test_util::{hosts, instance, NullBackend, NullPassthrough};
 
 
 	#[test]
@@ -367,10 +886,11 @@ mod tests {
                 "host1".to_owned(),
             ],
             interface: vec![
-                ServerInterface { port: 80, attr: ServerInterfaceAttribute::Http },
+                ServerInterface::new(80, ServerInterfaceAttribute::Http),
             ],
             location: None,
-            descriptor: Arc::new(NullBackend { key: "wakakaka".to_owned() })
+            descriptor: Arc::new(NullBackend { key: "wakakaka".to_owned() }),
+            source: None,
         }, OverwritePolicy::Error).unwrap();
 
         assert_eq!(format!("{:?}", reg.get_web_servers()), "[WebServer {host=[\"host1\"], interface=[Http:80]}]");
@@ -393,10 +913,11 @@ mod tests {
                 "host2".to_owned(),
             ],
             interface: vec![
-                ServerInterface { port: 80, attr: ServerInterfaceAttribute::Http },
+                ServerInterface::new(80, ServerInterfaceAttribute::Http),
             ],
             location: None,
-            descriptor: Arc::new(NullBackend { key: "wakakaka".to_owned() })
+            descriptor: Arc::new(NullBackend { key: "wakakaka".to_owned() }),
+            source: None,
         }, OverwritePolicy::Error).unwrap();
 
         assert_eq!(format!("{:?}", reg.get_web_servers()), "[WebServer {host=[\"host1\", \"host2\"], interface=[Http:80]}]");
@@ -406,10 +927,11 @@ mod tests {
                 "host1".to_owned(),
             ],
             interface: vec![
-                ServerInterface { port: 80, attr: ServerInterfaceAttribute::Http },
+                ServerInterface::new(80, ServerInterfaceAttribute::Http),
             ],
             location: Some("/test".to_owned()),
-            descriptor: Arc::new(NullBackend { key: "wakakaka".to_owned() })
+            descriptor: Arc::new(NullBackend { key: "wakakaka".to_owned() }),
+            source: None,
         }, OverwritePolicy::Error).unwrap();
 
         assert_eq!(format!("{:?}", reg.get_web_servers()), "[WebServer {host=[\"host1\"], interface=[Http:80]}, WebServer {host=[\"host2\"], interface=[Http:80]}]");
@@ -420,10 +942,11 @@ mod tests {
                 "host3".to_owned(),
             ],
             interface: vec![
-                ServerInterface { port: 80, attr: ServerInterfaceAttribute::Http },
+                ServerInterface::new(80, ServerInterfaceAttribute::Http),
             ],
             location: Some("/test2".to_owned()),
-            descriptor: Arc::new(NullBackend { key: "wakakaka".to_owned() })
+            descriptor: Arc::new(NullBackend { key: "wakakaka".to_owned() }),
+            source: None,
         }, OverwritePolicy::Error).unwrap();
 
         assert_eq!(format!("{:?}", reg.get_web_servers()), "[WebServer {host=[\"host1\"], interface=[Http:80]}, WebServer {host=[\"host2\"], interface=[Http:80]}, WebServer {host=[\"host3\"], interface=[Http:80]}]");
@@ -441,11 +964,12 @@ mod tests {
                 "host1".to_owned(),
             ],
             interface: vec![
-                ServerInterface { port: 80, attr: ServerInterfaceAttribute::Http },
-                ServerInterface { port: 81, attr: ServerInterfaceAttribute::Http },
+                ServerInterface::new(80, ServerInterfaceAttribute::Http),
+                ServerInterface::new(81, ServerInterfaceAttribute::Http),
             ],
             location: None,
-            descriptor: Arc::new(NullBackend { key: "wakakaka".to_owned() })
+            descriptor: Arc::new(NullBackend { key: "wakakaka".to_owned() }),
+            source: None,
         }, OverwritePolicy::Error).unwrap();
 
         assert_eq!(format!("{:?}", reg.get_web_servers()), "[WebServer {host=[\"host1\"], interface=[Http:80, Http:81]}]");
@@ -455,10 +979,11 @@ mod tests {
                 "host1".to_owned(),
             ],
             interface: vec![
-                ServerInterface { port: 80, attr: ServerInterfaceAttribute::Http },
+                ServerInterface::new(80, ServerInterfaceAttribute::Http),
             ],
             location: Some("/test".to_owned()),
-            descriptor: Arc::new(NullBackend { key: "wakakaka".to_owned() })
+            descriptor: Arc::new(NullBackend { key: "wakakaka".to_owned() }),
+            source: None,
         }, OverwritePolicy::Error).unwrap();
 
         assert_eq!(format!("{:?}", reg.get_web_servers()), "[WebServer {host=[\"host1\"], interface=[Http:80]}, WebServer {host=[\"host1\"], interface=[Http:81]}]");
@@ -468,41 +993,59 @@ mod tests {
                 "host1".to_owned(),
             ],
             interface: vec![
-                ServerInterface { port: 80, attr: ServerInterfaceAttribute::Http },
-                ServerInterface { port: 82, attr: ServerInterfaceAttribute::Http },
+                ServerInterface::new(80, ServerInterfaceAttribute::Http),
+                ServerInterface::new(82, ServerInterfaceAttribute::Http),
             ],
             location: Some("/test2".to_owned()),
-            descriptor: Arc::new(NullBackend { key: "wakakaka".to_owned() })
+            descriptor: Arc::new(NullBackend { key: "wakakaka".to_owned() }),
+            source: None,
         }, OverwritePolicy::Error).unwrap();
 
         assert_eq!(format!("{:?}", reg.get_web_servers()), "[WebServer {host=[\"host1\"], interface=[Http:80]}, WebServer {host=[\"host1\"], interface=[Http:81]}, WebServer {host=[\"host1\"], interface=[Http:82]}]");
     }
 
+	#[test]
+	fn registry_add_server_test_point_address_separation() {
+        let mut reg: Registry = std::default::Default::default();
+        let http = |address: &str| ServerInterface::new(80, ServerInterfaceAttribute::Http).with_address(address.to_owned());
+        let inst = |interface, location| instance(&["host1"], interface, location, "wakakaka");
+
+        reg.add_server(&inst(vec![http("10.0.0.1"), http("[::]")], None), OverwritePolicy::Error).unwrap();
+        reg.add_server(&inst(vec![http("[::]"), ServerInterface::unix("/run/x.sock", ServerInterfaceAttribute::Http)], Some("/test")), OverwritePolicy::Error).unwrap();
+
+        assert_eq!(format!("{:?}", reg.get_web_servers()), "[WebServer {host=[\"host1\"], interface=[Http:[::]:80]}, WebServer {host=[\"host1\"], interface=[Http:10.0.0.1:80]}, \
+            WebServer {host=[\"host1\"], interface=[Http:unix:/run/x.sock]}]");
+        assert_ne!(http("10.0.0.1"), http("[::]"));
+        assert_ne!(http("[::]"), ServerInterface::new(80, ServerInterfaceAttribute::Http));
+    }
+
 	#[test]
 	fn registry_add_server_test_point_illegal_input() {
         let mut reg: Registry = std::default::Default::default();
         
         assert_eq!(format!("{:?}", reg.get_web_servers()), "[]");
 
-        assert_eq!(match reg.add_server(&WebServerInstance {
+        assert!(matches!(match reg.add_server(&WebServerInstance {
             host: vec![],
-            interface: vec![ServerInterface { port: 80, attr: ServerInterfaceAttribute::Http }],
+            interface: vec![ServerInterface::new(80, ServerInterfaceAttribute::Http)],
             location: None,
-            descriptor: Arc::new(NullBackend { key: "wakaka".to_owned() })
+            descriptor: Arc::new(NullBackend { key: "wakaka".to_owned() }),
+            source: None,
         }, OverwritePolicy::Error) {
-            Err(e) => format!("{:?}", e),
+            Err(e) => e,
             Ok(_) => panic!("Exception untriggered"),
-        }, "Custom { kind: InvalidData, error: \"host is empty list\" }");
+        }, Error::EmptyHost));
 
-        assert_eq!(match reg.add_server(&WebServerInstance {
+        assert!(matches!(match reg.add_server(&WebServerInstance {
             host: vec!["aha".to_owned()],
             interface: vec![],
             location: None,
-            descriptor: Arc::new(NullBackend { key: "wakaka".to_owned() })
+            descriptor: Arc::new(NullBackend { key: "wakaka".to_owned() }),
+            source: None,
         }, OverwritePolicy::Error) {
-            Err(e) => format!("{:?}", e),
+            Err(e) => e,
             Ok(_) => panic!("Exception untriggered"),
-        }, "Custom { kind: InvalidData, error: \"interface is empty list\" }");
+        }, Error::EmptyInterface));
     }
 
 	#[test]
@@ -517,84 +1060,90 @@ mod tests {
                 "host2".to_owned(),
             ],
             interface: vec![
-                ServerInterface { port: 80, attr: ServerInterfaceAttribute::Http },
-                ServerInterface { port: 8080, attr: ServerInterfaceAttribute::Http },
+                ServerInterface::new(80, ServerInterfaceAttribute::Http),
+                ServerInterface::new(8080, ServerInterfaceAttribute::Http),
             ],
             location: None,
-            descriptor: Arc::new(NullBackend { key: "waka".to_owned() })
+            descriptor: Arc::new(NullBackend { key: "waka".to_owned() }),
+            source: None,
         }, OverwritePolicy::Error).unwrap();
 
         assert_eq!(format!("{:?}", reg.get_web_servers()), "[WebServer {host=[\"host1\", \"host2\"], interface=[Http:80, Http:8080]}]");
 
-        assert_eq!(match reg.add_server(&WebServerInstance {
+        assert!(matches!(match reg.add_server(&WebServerInstance {
             host: vec![
                 "host1".to_owned(),
                 "host2".to_owned(),
             ],
             interface: vec![
-                ServerInterface { port: 80, attr: ServerInterfaceAttribute::Http },
-                ServerInterface { port: 8080, attr: ServerInterfaceAttribute::Http },
+                ServerInterface::new(80, ServerInterfaceAttribute::Http),
+                ServerInterface::new(8080, ServerInterfaceAttribute::Http),
             ],
             location: None,
-            descriptor: Arc::new(NullBackend { key: "wakaka".to_owned() })
+            descriptor: Arc::new(NullBackend { key: "wakaka".to_owned() }),
+            source: None,
         }, OverwritePolicy::Error) {
-            Err(e) => format!("{:?}", e),
+            Err(e) => e,
             Ok(_) => panic!("Exception untriggered"),
-        }, "Custom { kind: AlreadyExists, error: \"Cannot overwrite existed server\" }");
+        }, Error::OverwriteConflict(c) if c.location.is_none()));
 
-        assert_eq!(match reg.add_server(&WebServerInstance {
+        assert!(matches!(match reg.add_server(&WebServerInstance {
             host: vec![
                 "host1".to_owned(),
             ],
             interface: vec![
-                ServerInterface { port: 80, attr: ServerInterfaceAttribute::Http },
-                ServerInterface { port: 8080, attr: ServerInterfaceAttribute::Http },
+                ServerInterface::new(80, ServerInterfaceAttribute::Http),
+                ServerInterface::new(8080, ServerInterfaceAttribute::Http),
             ],
             location: None,
-            descriptor: Arc::new(NullBackend { key: "wakaka".to_owned() })
+            descriptor: Arc::new(NullBackend { key: "wakaka".to_owned() }),
+            source: None,
         }, OverwritePolicy::Error) {
-            Err(e) => format!("{:?}", e),
+            Err(e) => e,
             Ok(_) => panic!("Exception untriggered"),
-        }, "Custom { kind: AlreadyExists, error: \"Cannot overwrite existed server\" }");
+        }, Error::OverwriteConflict(c) if c.location.is_none()));
 
-        assert_eq!(match reg.add_server(&WebServerInstance {
+        assert!(matches!(match reg.add_server(&WebServerInstance {
             host: vec![
                 "host1".to_owned(),
                 "host2".to_owned(),
             ],
             interface: vec![
-                ServerInterface { port: 80, attr: ServerInterfaceAttribute::Http },
+                ServerInterface::new(80, ServerInterfaceAttribute::Http),
             ],
             location: None,
-            descriptor: Arc::new(NullBackend { key: "wakaka".to_owned() })
+            descriptor: Arc::new(NullBackend { key: "wakaka".to_owned() }),
+            source: None,
         }, OverwritePolicy::Error) {
-            Err(e) => format!("{:?}", e),
+            Err(e) => e,
             Ok(_) => panic!("Exception untriggered"),
-        }, "Custom { kind: AlreadyExists, error: \"Cannot overwrite existed server\" }");
+        }, Error::OverwriteConflict(c) if c.location.is_none()));
 
-        assert_eq!(match reg.add_server(&WebServerInstance {
+        assert!(matches!(match reg.add_server(&WebServerInstance {
             host: vec![
                 "host1".to_owned(),
             ],
             interface: vec![
-                ServerInterface { port: 80, attr: ServerInterfaceAttribute::Http },
+                ServerInterface::new(80, ServerInterfaceAttribute::Http),
             ],
             location: None,
-            descriptor: Arc::new(NullBackend { key: "wakaka".to_owned() })
+            descriptor: Arc::new(NullBackend { key: "wakaka".to_owned() }),
+            source: None,
         }, OverwritePolicy::Error) {
-            Err(e) => format!("{:?}", e),
+            Err(e) => e,
             Ok(_) => panic!("Exception untriggered"),
-        }, "Custom { kind: AlreadyExists, error: \"Cannot overwrite existed server\" }");
+        }, Error::OverwriteConflict(c) if c.location.is_none()));
 
         reg.add_server(&WebServerInstance {
             host: vec![
                 "host1".to_owned(),
             ],
             interface: vec![
-                ServerInterface { port: 80, attr: ServerInterfaceAttribute::Http },
+                ServerInterface::new(80, ServerInterfaceAttribute::Http),
             ],
             location: None,
-            descriptor: Arc::new(NullBackend { key: "wakaka".to_owned() })
+            descriptor: Arc::new(NullBackend { key: "wakaka".to_owned() }),
+            source: None,
         }, OverwritePolicy::Ignore).unwrap();
 
         reg.add_server(&WebServerInstance {
@@ -602,13 +1151,109 @@ mod tests {
                 "host1".to_owned(),
             ],
             interface: vec![
-                ServerInterface { port: 80, attr: ServerInterfaceAttribute::Http },
+                ServerInterface::new(80, ServerInterfaceAttribute::Http),
             ],
             location: None,
-            descriptor: Arc::new(NullBackend { key: "wakaka".to_owned() })
+            descriptor: Arc::new(NullBackend { key: "wakaka".to_owned() }),
+            source: None,
         }, OverwritePolicy::Overwrite).unwrap();
     }
 
+	#[test]
+	fn registry_add_server_test_point_overwrite_log() {
+        let mut reg: Registry = std::default::Default::default();
+        let http = ServerInterface::new(80, ServerInterfaceAttribute::Http);
+        let inst = |key: &str, location| instance(&["host1"], vec![http.clone()], location, key)
+            .with_source(format!("src-{}", key));
+
+        reg.add_server(&inst("a", None), OverwritePolicy::Error).unwrap();
+        reg.add_server(&inst("b", Some("/x")), OverwritePolicy::Warn).unwrap();
+        assert!(reg.get_overwrite_log().is_empty());
+
+        reg.add_server(&inst("c", None), OverwritePolicy::Ignore).unwrap();
+        reg.add_server(&inst("d", None), OverwritePolicy::Overwrite).unwrap();
+        reg.add_server(&inst("e", Some("/x")), OverwritePolicy::Warn).unwrap();
+
+        let log = reg.get_overwrite_log();
+        assert_eq!(log.len(), 3);
+        assert_eq!((log[0].policy, log[0].conflict.existing.as_str(), log[0].conflict.incoming.as_str()), (OverwritePolicy::Ignore, "a", "c"));
+        assert_eq!((log[1].policy, log[1].conflict.existing.as_str(), log[1].conflict.incoming.as_str()), (OverwritePolicy::Overwrite, "a", "d"));
+        assert_eq!((log[2].policy, log[2].conflict.existing.as_str(), log[2].conflict.incoming.as_str()), (OverwritePolicy::Warn, "b", "e"));
+        assert_eq!(log.iter().filter(|r| r.is_warning()).count(), 1);
+        assert_eq!(log[2].to_string(), "overwrote at (host=[\"host1\"], interface=[Http:80], location=/x): e (from src-e) conflicts with b (from src-b)");
+
+        assert_eq!(reg.get_web_servers()[0].server.as_ref().unwrap().descriptor.get_key(), "d");
+        assert_eq!(reg.get_web_servers()[0].subservers["/x"].descriptor.get_key(), "e");
+
+        reg.clear();
+        assert!(reg.get_overwrite_log().is_empty());
+    }
+
+	#[test]
+	fn registry_remove_server_test_point_remerge() {
+        let mut reg: Registry = std::default::Default::default();
+        let http = ServerInterface::new(80, ServerInterfaceAttribute::Http);
+        let https = ServerInterface::new(443, ServerInterfaceAttribute::Https);
+
+        reg.add_server(&instance(&["host1", "host2"], vec![http.clone(), https.clone()], None, "root"), OverwritePolicy::Error).unwrap();
+        reg.add_server(&instance(&["host1"], vec![http.clone()], Some("/test"), "test"), OverwritePolicy::Error).unwrap();
+        assert_eq!(format!("{:?}", reg.get_web_servers()), "[WebServer {host=[\"host1\"], interface=[Http:80]}, WebServer {host=[\"host2\"], interface=[Http:80, Https:443]}, WebServer {host=[\"host1\"], interface=[Https:443]}]");
+
+        assert!(matches!(reg.remove_server(&hosts(&["host2"]), std::slice::from_ref(&http), Some("/test")), Err(Error::RouteNotFound { .. })));
+        assert!(matches!(reg.remove_server(&hosts(&["host3"]), std::slice::from_ref(&http), None), Err(Error::RouteNotFound { .. })));
+
+        reg.remove_server(&hosts(&["host1"]), std::slice::from_ref(&http), Some("/test")).unwrap();
+        assert_eq!(format!("{:?}", reg.get_web_servers()), "[WebServer {host=[\"host1\", \"host2\"], interface=[Http:80, Https:443]}]");
+
+        reg.remove_server(&hosts(&["host2"]), std::slice::from_ref(&https), None).unwrap();
+        assert_eq!(format!("{:?}", reg.get_web_servers()), "[WebServer {host=[\"host1\"], interface=[Http:80, Https:443]}, WebServer {host=[\"host2\"], interface=[Http:80]}]");
+
+        reg.remove_server(&hosts(&["host1", "host2"]), std::slice::from_ref(&http), None).unwrap();
+        assert_eq!(format!("{:?}", reg.get_web_servers()), "[WebServer {host=[\"host1\"], interface=[Https:443]}]");
+    }
+
+	#[test]
+	fn registry_replace_server_test_point_remerge() {
+        let mut reg: Registry = std::default::Default::default();
+        let http = ServerInterface::new(80, ServerInterfaceAttribute::Http);
+        let inst = |h, location, key| instance(h, vec![http.clone()], location, key);
+
+        reg.add_server(&inst(&["host1", "host2"], None, "root"), OverwritePolicy::Error).unwrap();
+        assert!(matches!(reg.replace_server(&inst(&["host1"], Some("/x"), "x")), Err(Error::RouteNotFound { .. })));
+
+        reg.replace_server(&inst(&["host1"], None, "other")).unwrap();
+        assert_eq!(format!("{:?}", reg.get_web_servers()), "[WebServer {host=[\"host1\"], interface=[Http:80]}, WebServer {host=[\"host2\"], interface=[Http:80]}]");
+        assert_eq!(reg.get_web_servers()[0].server.as_ref().unwrap().descriptor.get_key(), "other");
+        assert_eq!(reg.get_web_servers()[1].server.as_ref().unwrap().descriptor.get_key(), "root");
+
+        reg.replace_server(&inst(&["host2"], None, "other")).unwrap();
+        assert_eq!(format!("{:?}", reg.get_web_servers()), "[WebServer {host=[\"host1\", \"host2\"], interface=[Http:80]}]");
+        assert!(reg.get_overwrite_log().is_empty());
+    }
+
+	#[test]
+	fn registry_normalize_test_point_merge() {
+        let mut reg: Registry = std::default::Default::default();
+        let http = ServerInterface::new(80, ServerInterfaceAttribute::Http);
+        let https = ServerInterface::new(443, ServerInterfaceAttribute::Https);
+
+        reg.add_server(&instance(&["host1", "host2"], vec![http.clone()], None, "root"), OverwritePolicy::Error).unwrap();
+        reg.add_server(&instance(&["host1"], vec![http.clone()], Some("/x"), "x"), OverwritePolicy::Error).unwrap();
+        reg.add_server(&instance(&["host2"], vec![http.clone()], Some("/x"), "x"), OverwritePolicy::Error).unwrap();
+        reg.add_server(&instance(&["host1"], vec![https.clone()], Some("/x"), "x"), OverwritePolicy::Error).unwrap();
+        reg.add_server(&instance(&["host1"], vec![https.clone()], None, "root"), OverwritePolicy::Error).unwrap();
+        reg.add_server(&instance(&["host3"], vec![http.clone()], None, "root"), OverwritePolicy::Error).unwrap();
+        assert_eq!(format!("{:?}", reg.get_web_servers()), "[WebServer {host=[\"host1\"], interface=[Http:80]}, WebServer {host=[\"host2\"], interface=[Http:80]}, WebServer {host=[\"host1\"], interface=[Https:443]}, WebServer {host=[\"host3\"], interface=[Http:80]}]");
+
+        reg.normalize();
+        // host3 differs by its missing /x, so it is kept apart
+        assert_eq!(format!("{:?}", reg.get_web_servers()), "[WebServer {host=[\"host1\", \"host2\"], interface=[Http:80]}, WebServer {host=[\"host1\"], interface=[Https:443]}, WebServer {host=[\"host3\"], interface=[Http:80]}]");
+
+        // normalizing again is a no-op
+        reg.normalize();
+        assert_eq!(reg.get_web_servers().len(), 3);
+    }
+
 	#[test]
 	fn registry_add_server_test_point_complex_separation() {
         let mut reg: Registry = std::default::Default::default();
@@ -621,11 +1266,12 @@ mod tests {
                 "host2".to_owned(),
             ],
             interface: vec![
-                ServerInterface { port: 80, attr: ServerInterfaceAttribute::Http },
-                ServerInterface { port: 8080, attr: ServerInterfaceAttribute::Http },
+                ServerInterface::new(80, ServerInterfaceAttribute::Http),
+                ServerInterface::new(8080, ServerInterfaceAttribute::Http),
             ],
             location: None,
-            descriptor: Arc::new(NullBackend { key: "waka".to_owned() })
+            descriptor: Arc::new(NullBackend { key: "waka".to_owned() }),
+            source: None,
         }, OverwritePolicy::Error).unwrap();
 
         reg.add_server(&WebServerInstance {
@@ -634,13 +1280,113 @@ mod tests {
                 "host3".to_owned(),
             ],
             interface: vec![
-                ServerInterface { port: 80, attr: ServerInterfaceAttribute::Http },
-                ServerInterface { port: 443, attr: ServerInterfaceAttribute::Https },
+                ServerInterface::new(80, ServerInterfaceAttribute::Http),
+                ServerInterface::new(443, ServerInterfaceAttribute::Https),
             ],
             location: Some("/test".to_owned()),
-            descriptor: Arc::new(NullBackend { key: "wakakaka".to_owned() })
+            descriptor: Arc::new(NullBackend { key: "wakakaka".to_owned() }),
+            source: None,
         }, OverwritePolicy::Error).unwrap();
 
         assert_eq!(format!("{:?}", reg.get_web_servers()), "[WebServer {host=[\"host1\"], interface=[Http:80]}, WebServer {host=[\"host2\"], interface=[Http:80, Http:8080]}, WebServer {host=[\"host1\"], interface=[Http:8080]}, WebServer {host=[\"host3\"], interface=[Http:80, Https:443]}, WebServer {host=[\"host1\"], interface=[Https:443]}]");
 	}
+
+	#[test]
+	fn registry_http2_follows_port() {
+        #[derive(Debug)]
+        struct Grpc;
+        impl BackendDescriptor for Grpc {
+            fn get_key(&self) -> String {
+                "grpc".to_owned()
+            }
+            fn to_backend_config(&self) -> Result<String> {
+                Ok(String::new())
+            }
+            fn requires_http2(&self) -> bool {
+                true
+            }
+        }
+
+        let mut reg: Registry = std::default::Default::default();
+        let http = ServerInterface::new(80, ServerInterfaceAttribute::Http);
+        let https = ServerInterface::new(443, ServerInterfaceAttribute::Https);
+
+        reg.add_server(&instance(&["host1", "host2"], vec![http.clone(), https.clone()], None, "root"), OverwritePolicy::Error).unwrap();
+        reg.add_server(&WebServerInstance::new(hosts(&["host1"]), vec![https.clone()], Some("/rpc".to_owned()), Arc::new(Grpc)), OverwritePolicy::Error).unwrap();
+        // the port is shared, so host2 gets HTTP/2 as well
+        assert_eq!(format!("{:?}", reg.get_web_servers()), "[WebServer {host=[\"host1\"], interface=[Https:443+h2]}, WebServer {host=[\"host2\"], interface=[Http:80, Https:443+h2]}, WebServer {host=[\"host1\"], interface=[Http:80]}]");
+
+        reg.remove_server(&hosts(&["host1"]), std::slice::from_ref(&https), Some("/rpc")).unwrap();
+        assert_eq!(format!("{:?}", reg.get_web_servers()), "[WebServer {host=[\"host1\", \"host2\"], interface=[Https:443, Http:80]}]");
+	}
+
+	#[test]
+	fn registry_listen_options_follow_socket() {
+        let mut reg: Registry = std::default::Default::default();
+        let https = |options: ListenOptions| ServerInterface::new(443, ServerInterfaceAttribute::Https).with_options(options);
+        let pp = |range: &str| ListenOptions { proxy_protocol: Some(vec![range.to_owned()]), ..Default::default() };
+        let inst = |h, i, location| instance(&[h], vec![i], location, "wakakaka");
+
+        reg.add_server(&inst("host1", https(pp("10.0.0.0/8")), None), OverwritePolicy::Error).unwrap();
+        reg.add_server(&inst("host2", https(Default::default()), None), OverwritePolicy::Error).unwrap();
+        assert_eq!(format!("{:?}", reg.get_web_servers()), "[WebServer {host=[\"host1\"], interface=[Https:443+pp]}, WebServer {host=[\"host2\"], interface=[Https:443+pp]}]");
+        assert_eq!(reg.get_web_servers()[1].get_interface()[0].get_options().proxy_protocol, None);
+
+        // options asked for by an equal interface add up rather than split the host
+        let h3 = ListenOptions { http2: true, http3: true, ..pp("192.168.0.1") };
+        reg.add_server(&inst("host2", https(h3), Some("/a")), OverwritePolicy::Error).unwrap();
+        assert_eq!(format!("{:?}", reg.get_web_servers()), "[WebServer {host=[\"host1\"], interface=[Https:443+h2+pp]}, WebServer {host=[\"host2\"], interface=[Https:443+h2+h3+pp]}]");
+        let interface = &reg.get_web_servers()[0].get_interface()[0];
+        assert_eq!(interface.get_proxy_protocol(), Some(&vec!["10.0.0.0/8".to_owned(), "192.168.0.1".to_owned()]));
+        assert!(!interface.get_options().http3);
+
+        // other addresses of the port keep their own options
+        let bound = |address: &str, options: ListenOptions| https(options).with_address(address.to_owned());
+        reg.add_server(&inst("host3", bound("10.0.0.1", ListenOptions { http2: true, ..pp("10.0.0.0/8") }), None), OverwritePolicy::Error).unwrap();
+        reg.add_server(&inst("host4", bound("[::]", Default::default()), None), OverwritePolicy::Error).unwrap();
+        assert_eq!(format!("{:?}", &reg.get_web_servers()[2..]), "[WebServer {host=[\"host3\"], interface=[Https:10.0.0.1:443+h2+pp]}, WebServer {host=[\"host4\"], interface=[Https:[::]:443]}]");
+	}
+
+	#[test]
+	fn registry_passthrough_owns_interface() {
+        let mut reg: Registry = std::default::Default::default();
+        let http = ServerInterface::new(80, ServerInterfaceAttribute::Http);
+        let https = ServerInterface::new(443, ServerInterfaceAttribute::Https);
+        let passthrough = |h: &[&str]| WebServerInstance::new(hosts(h), vec![https.clone()], None, Arc::new(NullPassthrough));
+
+        reg.add_server(&passthrough(&["host1"]), OverwritePolicy::Error).unwrap();
+        reg.add_server(&instance(&["host1"], vec![http.clone()], Some("/a"), "a"), OverwritePolicy::Error).unwrap();
+        reg.add_server(&instance(&["host2"], vec![https.clone()], None, "root"), OverwritePolicy::Error).unwrap();
+        assert_eq!(reg.get_passthrough_listens(), vec!["443".to_owned()]);
+
+        // a location next to the passthrough, or a passthrough next to a location
+        let err = reg.add_server(&instance(&["host1"], vec![https.clone()], Some("/a"), "a"), OverwritePolicy::Error).err().unwrap();
+        assert_eq!(err.to_string(), "Cannot overwrite existed server (host=[\"host1\"], interface=[Https:443], location=/a): a conflicts with passthrough");
+        reg.add_server(&instance(&["host2"], vec![https.clone()], Some("/b"), "b"), OverwritePolicy::Error).unwrap();
+        assert!(reg.add_server(&passthrough(&["host2"]), OverwritePolicy::Error).is_err());
+
+        reg.add_server(&passthrough(&["host2"]), OverwritePolicy::Overwrite).unwrap();
+        assert_eq!(format!("{:?}", reg.get_web_servers()), "[WebServer {host=[\"host1\"], interface=[Https:443]}, WebServer {host=[\"host1\"], interface=[Http:80]}, WebServer {host=[\"host2\"], interface=[Https:443]}]");
+        assert!(reg.get_web_servers()[2].get_subservers().is_empty());
+        reg.add_server(&instance(&["host1"], vec![https.clone()], Some("/a"), "a"), OverwritePolicy::Overwrite).unwrap();
+        assert!(reg.get_web_servers().iter().all(|w| !w.host.contains(&"host1".to_owned()) || w.get_passthrough().is_none()));
+
+        // every route a passthrough displaces is reported, the root included
+        reg.clear();
+        reg.add_server(&instance(&["host3"], vec![https.clone()], None, "root"), OverwritePolicy::Error).unwrap();
+        reg.add_server(&instance(&["host3"], vec![https.clone()], Some("/a"), "a"), OverwritePolicy::Error).unwrap();
+        reg.add_server(&instance(&["host3"], vec![https.clone()], Some("/b"), "b"), OverwritePolicy::Error).unwrap();
+        let err = reg.add_server(&passthrough(&["host3"]), OverwritePolicy::Error).err().unwrap();
+        assert_eq!(err.to_string(), "Cannot pass through existed servers \
+            (host=[\"host3\"], interface=[Https:443], location=<root>): passthrough conflicts with root; \
+            (host=[\"host3\"], interface=[Https:443], location=/a): passthrough conflicts with a; \
+            (host=[\"host3\"], interface=[Https:443], location=/b): passthrough conflicts with b");
+        reg.add_server(&passthrough(&["host3"]), OverwritePolicy::Warn).unwrap();
+        let displaced: Vec<(Option<&str>, &str, bool)> = reg.get_overwrite_log().iter()
+            .map(|r| (r.conflict.location.as_deref(), r.conflict.existing.as_str(), r.is_warning()))
+            .collect();
+        assert_eq!(displaced, vec![(None, "root", true), (Some("/a"), "a", true), (Some("/b"), "b", true)]);
+        assert!(reg.get_web_servers()[0].get_passthrough().is_some());
+        assert!(reg.get_web_servers()[0].get_subservers().is_empty());
+	}
 }
@@ -0,0 +1,505 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use super::backend::{AcmeChallenge, CatchAll, Hsts, HttpsLoopback, HttpsRedirect, StreamProxy, ACME_CHALLENGE_LOCATION, CATCH_ALL_HOST};
+use super::config::*;
+use super::interface::*;
+use super::stream::{StreamDescriptor, StreamInterface, StreamProtocol, StreamRegistry, StreamServerInstance};
+use super::tls::RejectHandshake;
+
+fn interfaces(listens: &[ConfigListen], attr: ServerInterfaceAttribute, options: &ConfigListenOptions) -> Vec<ServerInterface> {
+    let https = attr == ServerInterfaceAttribute::Https;
+    let options = ListenOptions {
+        http2: https && options.http2,
+        http3: https && options.http3,
+        proxy_protocol: options.proxy_protocol.clone(),
+        default_server: options.default_server,
+        reuseport: options.reuseport,
+    };
+    listens.iter().map(|l| match (&l.address, l.is_unix()) {
+        (Some(address), true) => ServerInterface::unix(&address["unix:".len()..], attr),
+        (Some(address), false) => ServerInterface::new(l.port, attr).with_address(address.clone()),
+        (None, _) => ServerInterface::new(l.port, attr),
+    }.with_options(options.clone())).collect()
+}
+
+/// Splits `host` into groups that share one certificate, each paired with
+/// its HTTPS interfaces, since nginx takes one certificate per server block.
+fn https_groups<F>(host: &[String], host_tls: F, listens: &[ConfigListen], options: &ConfigListenOptions) -> Vec<(Vec<String>, Vec<ServerInterface>)>
+where
+    F: Fn(&str) -> Option<ConfigTls>,
+{
+    let mut groups: Vec<(Option<ConfigTls>, Vec<String>)> = Vec::new();
+    for h in host {
+        let tls = host_tls(h);
+        match groups.iter_mut().find(|(t, _)| *t == tls) {
+            Some((_, hosts)) => hosts.push(h.clone()),
+            None => groups.push((tls, vec![h.clone()])),
+        }
+    }
+    groups.into_iter().map(|(tls, hosts)| {
+        let tls = tls.map(|t| Arc::new(t) as Arc<dyn TlsDescriptor>);
+        let interface = interfaces(listens, ServerInterfaceAttribute::Https, options).into_iter()
+            .map(|i| match &tls {
+                Some(tls) => i.with_tls(tls.clone()),
+                None => i,
+            })
+            .collect();
+        (hosts, interface)
+    }).collect()
+}
+
+fn lower_server(cfg: &Config, index: usize, server: &ConfigServer) -> Result<Vec<WebServerInstance>> {
+    let template = match cfg.templates.get(&server.template) {
+        Some(t) => t,
+        None => return Err(Error::UnknownTemplate { template: server.template.clone() }),
+    };
+    // "/" is the root server of a WebServer, not one of its subservers
+    let location = match server.location.as_deref() {
+        None | Some("/") => None,
+        Some(loc) => Some(loc.to_owned()),
+    };
+    let source = server_path(index, server);
+    let backend: Arc<dyn BackendDescriptor> = Arc::new(server.backend.clone());
+
+    let mut instances = Vec::new();
+    match template {
+        ConfigServerTemplate::Http { https, port, listen, .. } => {
+            if server.host.is_empty() {
+                return Err(Error::EmptyHost);
+            }
+            let http = interfaces(&port.http, ServerInterfaceAttribute::Http, listen);
+            let backend: Arc<dyn BackendDescriptor> = match https {
+                ConfigHttpHttps::HSTS { duration, include_sub_domains, preload } => Arc::new(Hsts {
+                    max_age: *duration,
+                    include_sub_domains: *include_sub_domains,
+                    preload: *preload,
+                    inner: backend,
+                }),
+                _ => backend,
+            };
+            let serve_http = matches!(https, ConfigHttpHttps::Compatible | ConfigHttpHttps::Disabled);
+
+            if let ConfigHttpHttps::Disabled = https {
+                instances.push(WebServerInstance::new(server.host.clone(), http.clone(), location.clone(), backend)
+                    .with_source(source.clone()));
+            } else {
+                for (host, https_) in https_groups(&server.host, |h| server.host_tls(cfg, h), &port.https, listen) {
+                    let mut serve = if serve_http { http.clone() } else { Vec::new() };
+                    serve.extend(https_);
+                    instances.push(WebServerInstance::new(host, serve, location.clone(), backend.clone())
+                        .with_source(source.clone()));
+                }
+            }
+
+            // the redirect shares the location, so each path bounces to its own HTTPS counterpart;
+            // clients cannot follow it to a unix socket, so that falls back to the default port
+            let https_port = port.https.iter().find(|l| !l.is_unix()).map_or(443, |l| l.port);
+            if let (ConfigHttpHttps::Enforcing | ConfigHttpHttps::HSTS { .. }, false) = (https, port.https.is_empty()) {
+                instances.push(WebServerInstance::new(server.host.clone(), http, location, Arc::new(HttpsRedirect { port: https_port }))
+                    .with_source(source));
+            }
+        },
+        // lowered into a StreamRegistry by lower_streams
+        ConfigServerTemplate::Stream { .. } => {},
+    }
+    Ok(instances)
+}
+
+/// The challenge location of the ACME hosts of `server`, on the HTTP
+/// interfaces no earlier server in `served` has it on already.
+fn lower_acme_challenge(cfg: &Config, index: usize, server: &ConfigServer, served: &mut Vec<(String, ServerInterface, PathBuf)>) -> Vec<WebServerInstance> {
+    let (acme, port, listen) = match (server.acme(cfg), cfg.templates.get(&server.template)) {
+        (Some(acme), Some(ConfigServerTemplate::Http { port, listen, .. })) => (acme, port, listen),
+        _ => return Vec::new(),
+    };
+    let directory = acme.challenge_directory();
+    let http = interfaces(&port.http, ServerInterfaceAttribute::Http, listen);
+
+    // hosts left with the same interfaces share an instance, as they share a server block
+    let mut groups: Vec<(Vec<ServerInterface>, Vec<String>)> = Vec::new();
+    for host in &server.host {
+        let mut interface = Vec::new();
+        for i in &http {
+            let key = (host.clone(), i.clone(), directory.clone());
+            if !served.contains(&key) {
+                served.push(key);
+                interface.push(i.clone());
+            }
+        }
+        if interface.is_empty() {
+            continue;
+        }
+        match groups.iter_mut().find(|(i, _)| *i == interface) {
+            Some((_, hosts)) => hosts.push(host.clone()),
+            None => groups.push((interface, vec![host.clone()])),
+        }
+    }
+    groups.into_iter().map(|(interface, host)| {
+        let challenge = AcmeChallenge { directory: directory.clone() };
+        WebServerInstance::new(host, interface, Some(ACME_CHALLENGE_LOCATION.to_owned()), Arc::new(challenge))
+            .with_source(server_path(index, server))
+    }).collect()
+}
+
+fn lower_stream_server(cfg: &Config, index: usize, server: &ConfigServer) -> Result<Vec<StreamServerInstance>> {
+    let (protocol, ports, tls, preread) = match cfg.templates.get(&server.template) {
+        Some(ConfigServerTemplate::Stream { protocol, port, tls, preread }) => (protocol, port, tls, preread),
+        Some(ConfigServerTemplate::Http { .. }) => return Ok(Vec::new()),
+        None => return Err(Error::UnknownTemplate { template: server.template.clone() }),
+    };
+    let protocol = match protocol {
+        ConfigStreamProtocol::Tcp => StreamProtocol::Tcp,
+        ConfigStreamProtocol::Udp => StreamProtocol::Udp,
+    };
+    let tls = tls.clone().map(|t| Arc::new(t) as Arc<dyn TlsDescriptor>);
+    let backend: Arc<dyn StreamDescriptor> = Arc::new(StreamProxy { inner: server.backend.clone() });
+
+    Ok(ports.iter().map(|port| {
+        let mut inst = StreamServerInstance::new(StreamInterface::new(*port, protocol), backend.clone())
+            .with_source(server_path(index, server));
+        if let Some(tls) = &tls {
+            inst = inst.with_tls(tls.clone());
+        }
+        if *preread {
+            inst = inst.with_preread(server.host.clone());
+        }
+        inst
+    }).collect())
+}
+
+/// Adds every server of a stream template in `cfg` to `reg`; `lower`
+/// skips them.
+pub fn lower_streams(cfg: &Config, reg: &mut StreamRegistry) -> Result<()> {
+    for (index, server) in cfg.servers.iter().enumerate() {
+        let wrap = |error| Error::Server { index, name: server.name.clone(), error: Box::new(error) };
+        for inst in lower_stream_server(cfg, index, server).map_err(wrap)? {
+            reg.add_server(&inst).map_err(wrap)?;
+        }
+    }
+    Ok(())
+}
+
+/// The catch-all server of an http template, taking every interface of
+/// the template as its default server.
+fn lower_catch_all(cfg: &Config, name: &str, template: &ConfigServerTemplate) -> Option<WebServerInstance> {
+    let catch_all = cfg.catch_all(template)?;
+    let (https, port, listen, own) = match template {
+        ConfigServerTemplate::Http { https, port, listen, catch_all, .. } => (https, port, listen, catch_all.is_some()),
+        ConfigServerTemplate::Stream { .. } => return None,
+    };
+    let options = ConfigListenOptions { default_server: true, ..listen.clone() };
+    let mut interface = Vec::new();
+    if !matches!(https, ConfigHttpHttps::Only) {
+        interface.extend(interfaces(&port.http, ServerInterfaceAttribute::Http, &options));
+    }
+    if !matches!(https, ConfigHttpHttps::Disabled) {
+        let tls: Arc<dyn TlsDescriptor> = match catch_all.effective_tls(cfg) {
+            Some(tls) => Arc::new(tls),
+            None => Arc::new(RejectHandshake),
+        };
+        interface.extend(interfaces(&port.https, ServerInterfaceAttribute::Https, &options).into_iter().map(|i| i.with_tls(tls.clone())));
+    }
+    let source = if own { format!("templates.{}.catchAll", name) } else { "catchAll".to_owned() };
+    Some(WebServerInstance::new(vec![CATCH_ALL_HOST.to_owned()], interface, None, Arc::new(CatchAll { action: catch_all.action.clone() }))
+        .with_source(source))
+}
+
+/// Resolves every server in `cfg` against its template and adds it to `reg`,
+/// followed by the catch-all servers of the templates in use.
+pub fn lower<R: WebRegistry>(cfg: &Config, reg: &mut R, policy: OverwritePolicy) -> Result<()> {
+    let mut challenges = Vec::new();
+    for (index, server) in cfg.servers.iter().enumerate() {
+        let wrap = |error| Error::Server { index, name: server.name.clone(), error: Box::new(error) };
+        for inst in lower_server(cfg, index, server).map_err(wrap)? {
+            reg.add_server(&inst, policy).map_err(wrap)?;
+        }
+        for inst in lower_acme_challenge(cfg, index, server, &mut challenges) {
+            reg.add_server(&inst, policy).map_err(wrap)?;
+        }
+    }
+    for (name, template) in cfg.used_templates() {
+        if let Some(inst) = lower_catch_all(cfg, name, template) {
+            reg.add_server(&inst, policy).map_err(|error| Error::CatchAll { template: name.clone(), error: Box::new(error) })?;
+        }
+    }
+    Ok(())
+}
+
+/// Routes the hosts of the passthrough servers in `web` by SNI name on
+/// their HTTPS `listen`s, and every other name of such a `listen` to the
+/// loopback listener its remaining HTTPS servers move to.
+pub fn lower_passthrough(web: &Registry, reg: &mut StreamRegistry) -> Result<()> {
+    let listens = web.get_passthrough_listens();
+    for server in web.get_web_servers() {
+        let https = server.get_interface().iter().filter(|i| i.get_attr() == ServerInterfaceAttribute::Https);
+        let passthrough = server.get_passthrough();
+        for https in https.filter(|i| listens.contains(&i.get_listen())) {
+            let interface = match https.get_address() {
+                Some(address) => StreamInterface::new(https.get_port(), StreamProtocol::Tcp).with_address(address.to_owned()),
+                None => StreamInterface::new(https.get_port(), StreamProtocol::Tcp),
+            };
+            let inst = match (&passthrough, server.get_server()) {
+                (Some(descriptor), Some(route)) => {
+                    let inst = StreamServerInstance::new(interface, descriptor.clone()).with_preread(server.get_host().clone());
+                    match route.get_source() {
+                        Some(source) => inst.with_source(source.clone()),
+                        None => inst,
+                    }
+                },
+                _ => StreamServerInstance::new(interface, Arc::new(HttpsLoopback { listen: https.get_listen() })).with_preread(Vec::new()),
+            };
+            reg.add_server(&inst)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::nginx::{NginxHttpConfig, NginxStreamConfig};
+
+    fn parse(yaml: &str) -> Config {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn lower_renders_templates() {
+        let cfg = parse("
+templates:
+  web:
+    module: http
+    https: compatible
+    port:
+      http: 80
+      https: 443
+  plain:
+    module: http
+    https: disabled
+    port:
+      http: [8080, 8081]
+servers:
+  - template: web
+    host: [a.example.com, b.example.com]
+    backend:
+      type: proxy
+      target: 127.0.0.1:3000
+  - template: web
+    host: a.example.com
+    location: /static
+    backend: /srv/static
+  - template: plain
+    host: c.example.com
+    location: /
+    backend:
+      type: rewrite
+      target: https://a.example.com
+");
+        let mut reg: Registry = Default::default();
+        lower(&cfg, &mut reg, OverwritePolicy::Error).unwrap();
+
+        assert_eq!(format!("{:?}", reg.get_web_servers()), "[WebServer {host=[\"a.example.com\"], interface=[Http:80, Https:443]}, WebServer {host=[\"b.example.com\"], interface=[Http:80, Https:443]}, WebServer {host=[\"c.example.com\"], interface=[Http:8080, Http:8081]}]");
+        assert_eq!(reg.to_nginx_server_blocks().unwrap(), "\
+server {
+    listen 80;
+    listen 443 ssl;
+    server_name a.example.com;
+
+    location / {
+        proxy_pass http://127.0.0.1:3000;
+    }
+
+    location /static {
+        root /srv/static;
+    }
+}
+
+server {
+    listen 80;
+    listen 443 ssl;
+    server_name b.example.com;
+
+    location / {
+        proxy_pass http://127.0.0.1:3000;
+    }
+}
+
+server {
+    listen 8080;
+    listen 8081;
+    server_name c.example.com;
+
+    location / {
+        return 302 https://a.example.com;
+    }
+}");
+    }
+
+    #[test]
+    fn lower_reports_offending_server() {
+        let cfg = parse("
+templates:
+  web:
+    module: http
+    https: only
+    port: {}
+servers:
+  - template: web
+    host: a.example.com
+    backend: /srv/a
+  - name: second
+    template: missing
+    host: b.example.com
+    backend: /srv/b
+");
+        let mut reg: Registry = Default::default();
+        let err = lower(&cfg, &mut reg, OverwritePolicy::Error).unwrap_err();
+        match &err {
+            Error::Server { index: 1, name: Some(name), error } => {
+                assert_eq!(name, "second");
+                assert!(matches!(error.as_ref(), Error::UnknownTemplate { template } if template == "missing"));
+            },
+            e => panic!("unexpected error {:?}", e),
+        }
+        assert_eq!(err.to_string(), "servers[1] (second): unknown template \"missing\"");
+        // the message already holds the wrapped error; a chain printer must not repeat it
+        assert!(std::error::Error::source(&err).is_none());
+
+        let cfg = parse("
+templates:
+  web:
+    module: http
+    https: only
+    port: {}
+servers:
+  - name: first
+    template: web
+    host: a.example.com
+    backend: /srv/a
+  - template: web
+    host: a.example.com
+    backend: /srv/b
+");
+        let mut reg: Registry = Default::default();
+        let err = lower(&cfg, &mut reg, OverwritePolicy::Error).unwrap_err();
+        match &err {
+            Error::Server { index: 1, name: None, error } => match error.as_ref() {
+                Error::OverwriteConflict(c) => {
+                    assert_eq!(c.host, vec!["a.example.com".to_owned()]);
+                    assert_eq!(c.location, None);
+                    assert_eq!(c.existing, "/srv/a".parse::<ConfigBackend>().unwrap().get_key());
+                    assert_eq!(c.existing_source.as_deref(), Some("servers[0] (first)"));
+                    assert_eq!(c.incoming, "/srv/b".parse::<ConfigBackend>().unwrap().get_key());
+                    assert_eq!(c.incoming_source.as_deref(), Some("servers[1]"));
+                },
+                e => panic!("unexpected error {:?}", e),
+            },
+            e => panic!("unexpected error {:?}", e),
+        }
+        assert_eq!(err.to_string(), "servers[1]: Cannot overwrite existed server (host=[\"a.example.com\"], interface=[Https:443], location=<root>): \
+            {\"type\":\"file\",\"path\":\"/srv/b\"} (from servers[1]) conflicts with {\"type\":\"file\",\"path\":\"/srv/a\"} (from servers[0] (first))");
+
+        // two templates on one port, each asking for the shared catch-all
+        let cfg = parse("
+catchAll:
+  type: close
+templates:
+  a:
+    module: http
+    https: disabled
+  b:
+    module: http
+    https: disabled
+servers:
+  - template: a
+    host: a.example.com
+    backend: /srv/a
+  - template: b
+    host: b.example.com
+    backend: /srv/b
+");
+        let mut reg: Registry = Default::default();
+        let err = lower(&cfg, &mut reg, OverwritePolicy::Error).unwrap_err();
+        assert!(matches!(&err, Error::CatchAll { template, error } if template == "b" && matches!(error.as_ref(), Error::OverwriteConflict(_))));
+        assert_eq!(err.to_string(), "catch-all of templates.b: Cannot overwrite existed server (host=[\"_\"], interface=[Http:80], location=<root>): \
+            CatchAll { action: Close } (from catchAll) conflicts with CatchAll { action: Close } (from catchAll)");
+    }
+
+    #[test]
+    fn lower_adds_acme_challenge_once() {
+        let cfg = parse("
+templates:
+  web:
+    module: http
+    https: enforcing
+    port: {}
+    acme: {state: /var/lib/awsl/acme}
+servers:
+  - template: web
+    host: [a.example.com, b.example.com]
+    backend: /srv/www
+  - template: web
+    host: a.example.com
+    location: /static
+    backend: /srv/static
+  - template: web
+    host: c.example.com
+    location: /c
+    backend: /srv/c
+");
+        let mut reg: Registry = Default::default();
+        lower(&cfg, &mut reg, OverwritePolicy::Error).unwrap();
+        assert!(reg.get_overwrite_log().is_empty());
+        let challenges: Vec<_> = reg.get_web_servers().iter()
+            .filter(|s| s.get_subservers().contains_key(ACME_CHALLENGE_LOCATION))
+            .map(|s| (s.get_host().clone(), s.get_interface().clone()))
+            .collect();
+        let http = vec![ServerInterface::new(80, ServerInterfaceAttribute::Http)];
+        assert_eq!(challenges, vec![
+            (vec!["a.example.com".to_owned()], http.clone()),
+            (vec!["b.example.com".to_owned()], http.clone()),
+            (vec!["c.example.com".to_owned()], http),
+        ]);
+    }
+
+    #[test]
+    fn lower_splits_stream_servers() {
+        let cfg = parse("
+templates:
+  web: {module: http, https: disabled, port: {}}
+  db: {module: stream, port: [5432, 5433]}
+servers:
+  - template: web
+    host: a.example.com
+    backend: /srv/a
+  - template: db
+    backend: {type: proxy, target: 10.0.0.1:5432}
+  - name: clash
+    template: db
+    backend: {type: proxy, target: 10.0.0.2:5432}
+");
+        let mut reg: Registry = Default::default();
+        lower(&cfg, &mut reg, OverwritePolicy::Error).unwrap();
+        assert_eq!(reg.get_web_servers().len(), 1);
+
+        let mut streams: StreamRegistry = Default::default();
+        let err = lower_streams(&cfg, &mut streams).err().unwrap();
+        assert_eq!(err.to_string(), "servers[2] (clash): Cannot overwrite existed stream server (interface=Tcp:5432, name=<default>): \
+            {\"type\":\"proxy\",\"target\":[\"10.0.0.2:5432\"]} (from servers[2] (clash)) conflicts with {\"type\":\"proxy\",\"target\":[\"10.0.0.1:5432\"]} (from servers[1])");
+        assert_eq!(streams.to_nginx_stream_config().unwrap(), "\
+stream {
+
+    server {
+        listen 5432;
+        proxy_pass 10.0.0.1:5432;
+    }
+
+    server {
+        listen 5433;
+        proxy_pass 10.0.0.1:5432;
+    }
+
+}
+");
+    }
+}
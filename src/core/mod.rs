@@ -1,2 +1,13 @@
+pub mod error;
 pub mod interface;
 pub mod config;
+pub mod nginx;
+pub mod stream;
+pub mod backend;
+pub mod lower;
+pub mod tls;
+pub mod cert;
+pub mod acme;
+
+#[cfg(test)]
+pub(crate) mod test_util;
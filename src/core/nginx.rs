@@ -0,0 +1,478 @@
+use super::interface::*;
+use super::stream::*;
+
+/*
+
+http {
+
+    upstream awsl_0123456789abcdef {
+        server 10.0.0.1:8080;
+    }
+
+    server {
+        listen 80;
+        listen 443 ssl;
+        server_name some.site;
+
+        ssl_certificate /path/to/cert.pem;
+        ssl_certificate_key /path/to/key.pem;
+
+        location / {
+            blablabla
+        }
+
+        location /path1 {
+            blablabla
+        }
+    }
+
+}
+
+*/
+
+pub trait NginxHttpConfig {
+    type Err;
+    fn to_nginx_http_config(&self) -> Result<String, Self::Err>;
+    /// Blocks shared by servers, such as upstreams, each emitted once.
+    fn to_nginx_http_blocks(&self) -> Result<String, Self::Err>;
+    fn to_nginx_server_blocks(&self) -> Result<String, Self::Err>;
+}
+
+pub trait NginxStreamConfig {
+    type Err;
+    fn to_nginx_stream_config(&self) -> Result<String, Self::Err>;
+}
+
+const INDENT: &str = "    ";
+
+pub(crate) fn indent(text: &str, level: usize) -> String {
+    let prefix = INDENT.repeat(level);
+    text.lines()
+        .map(|l| if l.trim().is_empty() { String::new() } else { prefix.clone() + l })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Which address:port already took its socket-level `listen` parameters,
+/// since nginx rejects them on a second `listen` of the same socket.
+#[derive(Default)]
+struct SocketParameters {
+    given: Vec<(String, &'static str)>,
+}
+
+impl SocketParameters {
+    /// Whether `parameter` of `listen` is still to be given, claiming it.
+    fn claim(&mut self, listen: &str, parameter: &'static str) -> bool {
+        let given = (listen.to_owned(), parameter);
+        if self.given.contains(&given) {
+            false
+        } else {
+            self.given.push(given);
+            true
+        }
+    }
+
+    /// `default_server` and `reuseport` as asked for by `options`, on the
+    /// first `listen` of the socket of `listen` only; QUIC listeners count apart.
+    fn parameters(&mut self, listen: &str, quic: bool, options: &ListenOptions) -> String {
+        let key = if quic { format!("quic {}", socket_key(listen)) } else { socket_key(listen) };
+        let mut line = String::new();
+        let requested = [("default_server", options.default_server), ("reuseport", options.reuseport)];
+        for (parameter, _) in requested.iter().filter(|(_, on)| *on) {
+            if self.claim(&key, parameter) {
+                line += " ";
+                line += parameter;
+            }
+        }
+        line
+    }
+}
+
+/// Whether `interface` moved behind the stream listener of a passthrough;
+/// `passthrough` lists the HTTPS `listen`s taken over by the stream module.
+fn behind_loopback(interface: &ServerInterface, passthrough: &[String]) -> bool {
+    interface.get_attr() == ServerInterfaceAttribute::Https && !interface.is_unix() && passthrough.contains(&interface.get_listen())
+}
+
+/// The `listen` of `interface`, followed by its QUIC listener under HTTP/3.
+fn listen_lines(interface: &ServerInterface, passthrough: &[String], sockets: &mut SocketParameters) -> Vec<String> {
+    let https = interface.get_attr() == ServerInterfaceAttribute::Https;
+    let loopback = behind_loopback(interface, passthrough);
+    let listen = if loopback {
+        passthrough_socket(&interface.get_listen())
+    } else {
+        interface.get_listen()
+    };
+    let mut line = format!("listen {}", listen);
+    if https {
+        line += " ssl";
+    }
+    if interface.is_http2() {
+        line += " http2";
+    }
+    // the stream listener sends the client address ahead of the connection
+    if loopback || interface.get_proxy_protocol().is_some() {
+        line += " proxy_protocol";
+    }
+    line += &sockets.parameters(&listen, false, interface.get_options());
+    let mut lines = vec![line + ";"];
+
+    if https && interface.get_options().http3 && !interface.is_unix() {
+        let listen = interface.get_listen();
+        lines.push(format!("listen {} quic{};", listen, sockets.parameters(&listen, true, interface.get_options())));
+    }
+    lines
+}
+
+/// The `Alt-Svc` header advertising the QUIC listeners of `web`; nginx
+/// drops headers inherited from the server once a location adds its own,
+/// so it goes into every location.
+fn alt_svc(web: &WebServer) -> Option<String> {
+    let mut ports: Vec<u16> = web.get_interface().iter()
+        .filter(|i| i.get_attr() == ServerInterfaceAttribute::Https && i.get_options().http3 && !i.is_unix())
+        .map(|i| i.get_port())
+        .collect();
+    ports.sort_unstable();
+    ports.dedup();
+    if ports.is_empty() {
+        return None;
+    }
+    let services: Vec<String> = ports.iter().map(|p| format!("h3=\":{}\"; ma=86400", p)).collect();
+    Some(format!("add_header Alt-Svc '{}' always;", services.join(", ")))
+}
+
+/// `set_real_ip_from` for the trusted addresses of every interface of `web`
+/// that takes the PROXY protocol, the stream listener of a passthrough
+/// sending it over a unix socket.
+fn real_ip(web: &WebServer, passthrough: &[String]) -> Vec<String> {
+    let loopback = "unix:".to_owned();
+    let mut ranges: Vec<&String> = Vec::new();
+    for range in web.get_interface().iter().filter_map(|i| i.get_proxy_protocol()).flatten() {
+        if !ranges.contains(&range) {
+            ranges.push(range);
+        }
+    }
+    if web.get_interface().iter().any(|i| behind_loopback(i, passthrough)) && !ranges.contains(&&loopback) {
+        ranges.push(&loopback);
+    }
+    let mut lines: Vec<String> = ranges.iter().map(|r| format!("set_real_ip_from {};", r)).collect();
+    if !lines.is_empty() {
+        lines.push("real_ip_header proxy_protocol;".to_owned());
+    }
+    lines
+}
+
+fn location_block(location: &str, descriptor: &dyn BackendDescriptor, header: Option<&str>) -> Result<String> {
+    let mut config = descriptor.to_backend_config()?;
+    if let Some(header) = header {
+        config = format!("{}\n{}", header, config);
+    }
+    Ok(format!("location {} {{\n{}\n}}", location, indent(&config, 1)))
+}
+
+fn server_block(web: &WebServer, passthrough: &[String], sockets: &mut SocketParameters) -> Result<String> {
+    let mut lines = Vec::new();
+    for interface in web.get_interface() {
+        lines.extend(listen_lines(interface, passthrough, sockets));
+    }
+    lines.push(format!("server_name {};", web.get_host().join(" ")));
+    let real_ip = real_ip(web, passthrough);
+    if !real_ip.is_empty() {
+        lines.push(String::new());
+        lines.extend(real_ip);
+    }
+    if let Some(tls) = web.get_tls()? {
+        lines.push(String::new());
+        lines.push(tls.to_tls_config()?);
+    }
+
+    let alt_svc = alt_svc(web);
+    if let Some(server) = web.get_server() {
+        if web.get_subservers().contains_key("/") {
+            return Err(Error::DuplicateLocation {
+                host: web.get_host().clone(),
+                location: "/".to_owned(),
+            });
+        }
+        lines.push(String::new());
+        lines.push(location_block("/", server.get_descriptor().as_ref(), alt_svc.as_deref())?);
+    }
+    // subservers is a BTreeMap, so locations are emitted in a stable order
+    for (location, route) in web.get_subservers() {
+        lines.push(String::new());
+        lines.push(location_block(location, route.get_descriptor().as_ref(), alt_svc.as_deref())?);
+    }
+
+    let mut keys = Vec::new();
+    for route in web.get_server().into_iter().chain(web.get_subservers().values()) {
+        for block in route.get_descriptor().get_server_blocks() {
+            let key = block.get_key();
+            if !keys.contains(&key) {
+                lines.push(String::new());
+                lines.push(block.to_backend_config()?);
+                keys.push(key);
+            }
+        }
+    }
+
+    Ok(format!("server {{\n{}\n}}", indent(&lines.join("\n"), 1)))
+}
+
+impl NginxHttpConfig for Registry {
+    type Err = Error;
+
+    fn to_nginx_http_config(&self) -> Result<String, Self::Err> {
+        let mut body = self.to_nginx_http_blocks()?;
+        if !body.is_empty() {
+            body += "\n\n";
+        }
+        body += &self.to_nginx_server_blocks()?;
+        Ok(String::from("http {\n\n") + &indent(&body, 1) + "\n\n}\n")
+    }
+
+    fn to_nginx_http_blocks(&self) -> Result<String, Self::Err> {
+        let mut keys = Vec::new();
+        let mut blocks = Vec::new();
+        for web in self.get_web_servers() {
+            for route in web.get_server().into_iter().chain(web.get_subservers().values()) {
+                for block in route.get_descriptor().get_http_blocks() {
+                    let key = block.get_key();
+                    if !keys.contains(&key) {
+                        blocks.push(block.to_backend_config()?);
+                        keys.push(key);
+                    }
+                }
+            }
+        }
+        Ok(blocks.join("\n\n"))
+    }
+
+    fn to_nginx_server_blocks(&self) -> Result<String, Self::Err> {
+        let passthrough = self.get_passthrough_listens();
+        let mut sockets = SocketParameters::default();
+        let mut blocks = Vec::new();
+        // passed-through hosts are served by the stream module
+        for web in self.get_web_servers().iter().filter(|w| w.get_passthrough().is_none()) {
+            blocks.push(server_block(web, &passthrough, &mut sockets)?);
+        }
+        Ok(blocks.join("\n\n"))
+    }
+}
+
+fn stream_listen_line(server: &StreamServer) -> String {
+    let interface = server.get_interface();
+    let mut line = format!("listen {}", interface.get_listen());
+    if interface.get_protocol() == StreamProtocol::Udp {
+        line += " udp";
+    }
+    if server.get_tls().is_some() {
+        line += " ssl";
+    }
+    line + ";"
+}
+
+fn sni_map(variable: &str, entries: &[String]) -> String {
+    // hostnames lets the map take server_name style wildcards
+    let map = std::iter::once("hostnames;".to_owned()).chain(entries.iter().cloned()).collect::<Vec<_>>();
+    format!("map $ssl_preread_server_name {} {{\n{}\n}}", variable, indent(&map.join("\n"), 1))
+}
+
+/// A server block, preceded by the map that picks its backend by SNI name
+/// if it prereads. nginx sends the PROXY protocol for a whole server, so
+/// once a target takes it, the others are reached through a relay server
+/// that drops it again.
+fn stream_server_block(server: &StreamServer) -> Result<String> {
+    let mut blocks = Vec::new();
+    let mut relay_blocks = Vec::new();
+    let mut lines = vec![stream_listen_line(server)];
+    if let Some(tls) = server.get_tls() {
+        lines.push(String::new());
+        lines.push(tls.to_tls_config()?);
+        lines.push(String::new());
+    }
+    if server.is_preread() {
+        let listen = server.get_interface().get_listen();
+        let routes: Vec<(&str, &StreamRoute)> = server.get_routes().iter().map(|(name, route)| (name.as_str(), route))
+            .chain(server.get_default().map(|route| ("default", route)))
+            .collect();
+        let proxy_protocol = routes.iter().any(|(_, route)| route.get_descriptor().takes_proxy_protocol());
+        let mut map = Vec::new();
+        let mut relay = Vec::new();
+        for (name, route) in routes {
+            let target = route.get_descriptor().get_pass_target()?;
+            if proxy_protocol && !route.get_descriptor().takes_proxy_protocol() {
+                map.push(format!("{} {};", name, relay_socket(&listen)));
+                relay.push(format!("{} {};", name, target));
+            } else {
+                map.push(format!("{} {};", name, target));
+            }
+        }
+        let variable = format!("$awsl_sni_{}", listen_id(&listen));
+        blocks.push(sni_map(&variable, &map));
+        lines.push("ssl_preread on;".to_owned());
+        if proxy_protocol {
+            lines.push("proxy_protocol on;".to_owned());
+        }
+        lines.push(format!("proxy_pass {};", variable));
+
+        if !relay.is_empty() {
+            let variable = format!("$awsl_relay_{}", listen_id(&listen));
+            relay_blocks.push(sni_map(&variable, &relay));
+            let relay_lines = [format!("listen {} proxy_protocol;", relay_socket(&listen)), "ssl_preread on;".to_owned(), format!("proxy_pass {};", variable)];
+            relay_blocks.push(format!("server {{\n{}\n}}", indent(&relay_lines.join("\n"), 1)));
+        }
+    } else if let Some(route) = server.get_default() {
+        lines.push(route.get_descriptor().to_backend_config()?);
+    }
+    blocks.push(format!("server {{\n{}\n}}", indent(&lines.join("\n"), 1)));
+    blocks.extend(relay_blocks);
+    Ok(blocks.join("\n\n"))
+}
+
+impl NginxStreamConfig for StreamRegistry {
+    type Err = Error;
+
+    fn to_nginx_stream_config(&self) -> Result<String, Self::Err> {
+        let mut keys = Vec::new();
+        let mut blocks = Vec::new();
+        for server in self.get_stream_servers() {
+            for route in server.get_default().into_iter().chain(server.get_routes().values()) {
+                for block in route.get_descriptor().get_http_blocks() {
+                    let key = block.get_key();
+                    if !keys.contains(&key) {
+                        blocks.push(block.to_backend_config()?);
+                        keys.push(key);
+                    }
+                }
+            }
+        }
+        for server in self.get_stream_servers() {
+            blocks.push(stream_server_block(server)?);
+        }
+        Ok(String::from("stream {\n\n") + &indent(&blocks.join("\n\n"), 1) + "\n\n}\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use super::super::test_util::{instance, NullTls};
+
+    #[test]
+    fn nginx_render_empty_registry() {
+        let reg: Registry = Default::default();
+        assert_eq!(reg.to_nginx_http_config().unwrap(), "http {\n\n\n\n}\n");
+    }
+
+    #[test]
+    fn nginx_render_server_blocks() {
+        let mut reg: Registry = Default::default();
+        let http = ServerInterface::new(80, ServerInterfaceAttribute::Http);
+        let https = ServerInterface::new(443, ServerInterfaceAttribute::Https);
+
+        reg.add_server(&instance(&["host1", "host2"], vec![http.clone(), https.clone()], None, "root"), OverwritePolicy::Error).unwrap();
+        reg.add_server(&instance(&["host1", "host2"], vec![http.clone(), https.clone()], Some("/b"), "b"), OverwritePolicy::Error).unwrap();
+        reg.add_server(&instance(&["host1", "host2"], vec![http.clone(), https.clone()], Some("/a"), "a"), OverwritePolicy::Error).unwrap();
+        reg.add_server(&instance(&["host3"], vec![http.clone()], Some("/c"), "c"), OverwritePolicy::Error).unwrap();
+
+        assert_eq!(reg.to_nginx_http_config().unwrap(), "\
+http {
+
+    server {
+        listen 80;
+        listen 443 ssl;
+        server_name host1 host2;
+
+        location / {
+            return 200 \"root\";
+        }
+
+        location /a {
+            return 200 \"a\";
+        }
+
+        location /b {
+            return 200 \"b\";
+        }
+    }
+
+    server {
+        listen 80;
+        server_name host3;
+
+        location /c {
+            return 200 \"c\";
+        }
+    }
+
+}
+");
+    }
+
+    #[test]
+    fn nginx_render_duplicated_root_location() {
+        let mut reg: Registry = Default::default();
+        let http = ServerInterface::new(80, ServerInterfaceAttribute::Http);
+
+        reg.add_server(&instance(&["host1"], vec![http.clone()], None, "root"), OverwritePolicy::Error).unwrap();
+        reg.add_server(&instance(&["host1"], vec![http.clone()], Some("/"), "slash"), OverwritePolicy::Error).unwrap();
+
+        assert!(matches!(reg.to_nginx_http_config(), Err(Error::DuplicateLocation { .. })));
+    }
+
+    #[test]
+    fn nginx_render_tls() {
+        let mut reg: Registry = Default::default();
+        let http = ServerInterface::new(80, ServerInterfaceAttribute::Http);
+        let https = |port, key: &str| ServerInterface::new(port, ServerInterfaceAttribute::Https)
+            .with_tls(Arc::new(NullTls { key: key.to_owned() }));
+
+        reg.add_server(&instance(&["host1"], vec![http, https(443, "a"), https(8443, "a")], None, "root"), OverwritePolicy::Error).unwrap();
+        assert_eq!(reg.to_nginx_server_blocks().unwrap(), "\
+server {
+    listen 80;
+    listen 443 ssl;
+    listen 8443 ssl;
+    server_name host1;
+
+    ssl_certificate a.pem;
+
+    location / {
+        return 200 \"root\";
+    }
+}");
+
+        reg.clear();
+        reg.add_server(&instance(&["host1"], vec![https(443, "a"), https(8443, "b")], None, "root"), OverwritePolicy::Error).unwrap();
+        assert!(matches!(reg.to_nginx_server_blocks(), Err(Error::TlsMismatch { .. })));
+    }
+
+    #[test]
+    fn nginx_render_socket_parameters_once() {
+        let mut reg: Registry = Default::default();
+        let options = ListenOptions { default_server: true, reuseport: true, ..Default::default() };
+        let http = |address: Option<&str>, port| {
+            let interface = ServerInterface::new(port, ServerInterfaceAttribute::Http).with_options(options.clone());
+            match address {
+                Some(address) => interface.with_address(address.to_owned()),
+                None => interface,
+            }
+        };
+
+        // every spelling of the wildcard binds one socket
+        reg.add_server(&instance(&["host1"], vec![http(None, 80), http(Some("[::]"), 80)], None, "a"), OverwritePolicy::Error).unwrap();
+        reg.add_server(&instance(&["host2"], vec![http(Some("*"), 80), http(Some("[0::0]"), 80)], None, "b"), OverwritePolicy::Error).unwrap();
+        reg.add_server(&instance(&["host3"], vec![http(Some("0.0.0.0"), 80), http(Some("10.0.0.1"), 80)], None, "c"), OverwritePolicy::Error).unwrap();
+        let blocks = reg.to_nginx_server_blocks().unwrap();
+        let listens: Vec<&str> = blocks.lines().map(str::trim).filter(|l| l.starts_with("listen ")).collect();
+        assert_eq!(listens, vec![
+            "listen 80 default_server reuseport;",
+            "listen [::]:80 default_server reuseport;",
+            "listen *:80;",
+            "listen [0::0]:80;",
+            "listen 0.0.0.0:80;",
+            "listen 10.0.0.1:80 default_server reuseport;",
+        ]);
+    }
+}
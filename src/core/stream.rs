@@ -0,0 +1,317 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/*
+
+stream {
+
+    server {
+        listen 5432;
+        proxy_pass 10.0.0.5:5432;
+    }
+
+    map $ssl_preread_server_name $awsl_sni_443 {
+        hostnames;
+        a.example.com 10.0.0.1:443;
+    }
+
+    server {
+        listen 443;
+        ssl_preread on;
+        proxy_pass $awsl_sni_443;
+    }
+
+}
+
+*/
+
+pub use super::error::{Error, Result, StreamConflict};
+use super::interface::{BackendDescriptor, TlsDescriptor};
+
+/// `listen` as it goes into socket paths and variable names.
+pub fn listen_id(listen: &str) -> String {
+    listen.chars()
+        .filter(|c| *c != '[' && *c != ']')
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Where the HTTPS servers of `listen` move once a passthrough takes
+/// it over; nginx makes the socket writable for its workers.
+pub fn passthrough_socket(listen: &str) -> String {
+    format!("unix:/run/awsl-https-{}.sock", listen_id(listen))
+}
+
+/// Where the passed-through names of `listen` are relayed to drop the
+/// PROXY protocol header sent for the HTTPS servers behind it.
+pub fn relay_socket(listen: &str) -> String {
+    format!("unix:/run/awsl-relay-{}.sock", listen_id(listen))
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StreamProtocol {
+    Tcp,
+    Udp,
+}
+
+/// A port of nginx's stream module, on every address unless bound to one;
+/// a TCP and a UDP listener on the same port are distinct.
+#[derive(Clone, PartialEq)]
+pub struct StreamInterface {
+    address: Option<String>,
+    port: u16,
+    protocol: StreamProtocol,
+}
+
+impl StreamInterface {
+    pub fn new(port: u16, protocol: StreamProtocol) -> Self {
+        StreamInterface { address: None, port, protocol }
+    }
+
+    /// Binds the listener to `address`, an IPv4 address or a bracketed IPv6 one.
+    pub fn with_address(mut self, address: String) -> Self {
+        self.address = Some(address);
+        self
+    }
+
+    pub fn get_port(&self) -> u16 {
+        self.port
+    }
+
+    /// What nginx's `listen` takes: `port` or `address:port`.
+    pub fn get_listen(&self) -> String {
+        match &self.address {
+            Some(address) => format!("{}:{}", address, self.port),
+            None => self.port.to_string(),
+        }
+    }
+
+    pub fn get_protocol(&self) -> StreamProtocol {
+        self.protocol
+    }
+}
+
+impl std::fmt::Debug for StreamInterface {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}:{}", self.protocol, self.get_listen())
+    }
+}
+
+/// A backend that can take connections of a stream server.
+/// `to_backend_config` renders the directives of a server that forwards
+/// every connection to it, and `get_http_blocks` the blocks it needs at
+/// stream level.
+pub trait StreamDescriptor: BackendDescriptor {
+    /// The `proxy_pass` value, for listeners that pick a backend by SNI name.
+    fn get_pass_target(&self) -> Result<String>;
+    /// Whether the target reads the client address from a PROXY protocol header.
+    fn takes_proxy_protocol(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Clone)]
+pub struct StreamServerInstance {
+    interface: StreamInterface,
+    tls: Option<Arc<dyn TlsDescriptor>>,
+    preread: bool,
+    name: Vec<String>, // SNI names, only read when preread
+    descriptor: Arc<dyn StreamDescriptor>,
+    source: Option<String>,
+}
+
+impl StreamServerInstance {
+    pub fn new(interface: StreamInterface, descriptor: Arc<dyn StreamDescriptor>) -> Self {
+        StreamServerInstance { interface, tls: None, preread: false, name: Vec::new(), descriptor, source: None }
+    }
+
+    /// Terminates TLS on the listener.
+    pub fn with_tls(mut self, tls: Arc<dyn TlsDescriptor>) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Takes the connections whose SNI name matches `name`, without
+    /// terminating TLS; an empty `name` takes the rest.
+    pub fn with_preread(mut self, name: Vec<String>) -> Self {
+        self.preread = true;
+        self.name = name;
+        self
+    }
+
+    pub fn with_source(mut self, source: String) -> Self {
+        self.source = Some(source);
+        self
+    }
+}
+
+/// A descriptor registered in a `StreamServer`, together with its origin.
+#[derive(Clone)]
+pub struct StreamRoute {
+    descriptor: Arc<dyn StreamDescriptor>,
+    source: Option<String>,
+}
+
+impl StreamRoute {
+    pub fn get_descriptor(&self) -> &Arc<dyn StreamDescriptor> {
+        &self.descriptor
+    }
+}
+
+/// Everything registered on one stream listener.
+#[derive(Clone)]
+pub struct StreamServer {
+    interface: StreamInterface,
+    tls: Option<Arc<dyn TlsDescriptor>>,
+    preread: bool,
+    routes: BTreeMap<String, StreamRoute>, // by SNI name
+    default: Option<StreamRoute>,
+}
+
+impl StreamServer {
+    pub fn get_interface(&self) -> &StreamInterface {
+        &self.interface
+    }
+
+    pub fn get_tls(&self) -> Option<&Arc<dyn TlsDescriptor>> {
+        self.tls.as_ref()
+    }
+
+    pub fn is_preread(&self) -> bool {
+        self.preread
+    }
+
+    pub fn get_routes(&self) -> &BTreeMap<String, StreamRoute> {
+        &self.routes
+    }
+
+    /// The route of connections no SNI name matched, or of every
+    /// connection when the listener does not preread.
+    pub fn get_default(&self) -> Option<&StreamRoute> {
+        self.default.as_ref()
+    }
+}
+
+impl std::fmt::Debug for StreamServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "StreamServer {{interface={:?}, name={:?}}}", self.interface, self.routes.keys().collect::<Vec<_>>())
+    }
+}
+
+/// The stream servers of a config, apart from the web servers since nginx
+/// renders them into a `stream {}` block of their own. There are no
+/// overwrite policies: servers share a listener only if they agree on TLS
+/// and preread, and never claim the same name.
+#[derive(Default)]
+pub struct StreamRegistry {
+    servers: Vec<StreamServer>,
+}
+
+impl StreamRegistry {
+    pub fn get_stream_servers(&self) -> &Vec<StreamServer> {
+        &self.servers
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.servers.is_empty()
+    }
+
+    /// Adds `inst` to the listener of its interface. Re-adding a descriptor
+    /// with the same key is a no-op; a different one is an error.
+    pub fn add_server(&mut self, inst: &StreamServerInstance) -> Result<&mut Self> {
+        let index = match self.servers.iter().position(|s| s.interface == inst.interface) {
+            Some(index) => index,
+            None => {
+                self.servers.push(StreamServer {
+                    interface: inst.interface.clone(),
+                    tls: inst.tls.clone(),
+                    preread: inst.preread,
+                    routes: BTreeMap::new(),
+                    default: None,
+                });
+                self.servers.len() - 1
+            },
+        };
+        let server = &mut self.servers[index];
+        let tls_key = |tls: Option<&Arc<dyn TlsDescriptor>>| tls.map(|t| t.get_key());
+        if server.preread != inst.preread || tls_key(server.tls.as_ref()) != tls_key(inst.tls.as_ref()) {
+            return Err(Error::StreamMismatch { interface: inst.interface.clone() });
+        }
+
+        let names: Vec<Option<&String>> = if inst.name.is_empty() {
+            vec![None]
+        } else {
+            inst.name.iter().map(Some).collect()
+        };
+        // check every name first, so a failed add changes nothing
+        for name in &names {
+            let existing = match name {
+                Some(name) => server.routes.get(*name),
+                None => server.default.as_ref(),
+            };
+            if let Some(existing) = existing {
+                if existing.descriptor.get_key() != inst.descriptor.get_key() {
+                    return Err(Error::StreamConflict(Box::new(StreamConflict {
+                        interface: inst.interface.clone(),
+                        name: name.cloned(),
+                        existing: existing.descriptor.get_key(),
+                        existing_source: existing.source.clone(),
+                        incoming: inst.descriptor.get_key(),
+                        incoming_source: inst.source.clone(),
+                    })));
+                }
+            }
+        }
+        let route = StreamRoute { descriptor: inst.descriptor.clone(), source: inst.source.clone() };
+        for name in names {
+            match name {
+                Some(name) => { server.routes.entry(name.clone()).or_insert_with(|| route.clone()); },
+                None => { server.default.get_or_insert_with(|| route.clone()); },
+            }
+        }
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_util::{hosts, NullBackend, NullTls};
+
+    fn instance(port: u16, key: &str) -> StreamServerInstance {
+        StreamServerInstance::new(StreamInterface::new(port, StreamProtocol::Tcp), Arc::new(NullBackend { key: key.to_owned() }))
+    }
+
+    #[test]
+    fn stream_add_server() {
+        let mut reg: StreamRegistry = Default::default();
+        reg.add_server(&instance(5432, "a")).unwrap();
+        reg.add_server(&instance(5432, "a")).unwrap();
+        let udp = StreamServerInstance::new(StreamInterface::new(5432, StreamProtocol::Udp), Arc::new(NullBackend { key: "b".to_owned() }));
+        reg.add_server(&udp).unwrap();
+        reg.add_server(&instance(443, "a").with_preread(hosts(&["a.example.com"]))).unwrap();
+        reg.add_server(&instance(443, "b").with_preread(hosts(&["b.example.com", "c.example.com"]))).unwrap();
+        assert_eq!(format!("{:?}", reg.get_stream_servers()), "[StreamServer {interface=Tcp:5432, name=[]}, StreamServer {interface=Udp:5432, name=[]}, \
+            StreamServer {interface=Tcp:443, name=[\"a.example.com\", \"b.example.com\", \"c.example.com\"]}]");
+
+        let err = reg.add_server(&instance(5432, "c").with_source("servers[3]".to_owned())).err().unwrap();
+        assert_eq!(err.to_string(), "Cannot overwrite existed stream server (interface=Tcp:5432, name=<default>): c (from servers[3]) conflicts with a");
+        // one taken name fails the whole instance
+        let err = reg.add_server(&instance(443, "d").with_preread(hosts(&["d.example.com", "c.example.com"]))).err().unwrap();
+        assert!(matches!(err, Error::StreamConflict(c) if c.name.as_deref() == Some("c.example.com")));
+        assert!(!reg.get_stream_servers()[2].get_routes().contains_key("d.example.com"));
+
+        assert!(matches!(reg.add_server(&instance(443, "a")).err(), Some(Error::StreamMismatch { .. })));
+        assert!(matches!(reg.add_server(&instance(5432, "a").with_tls(Arc::new(NullTls { key: "tls".to_owned() }))).err(), Some(Error::StreamMismatch { .. })));
+
+        // each address of a port is a listener of its own
+        let bound = |address: &str| StreamServerInstance::new(StreamInterface::new(443, StreamProtocol::Tcp).with_address(address.to_owned()), Arc::new(NullBackend { key: "e".to_owned() }));
+        reg.add_server(&bound("10.0.0.1")).unwrap();
+        reg.add_server(&bound("[::]")).unwrap();
+        let listens: Vec<String> = reg.get_stream_servers().iter().map(|s| s.get_interface().get_listen()).collect();
+        assert_eq!(listens, vec!["5432", "5432", "443", "10.0.0.1:443", "[::]:443"]);
+        assert_eq!(passthrough_socket("443"), "unix:/run/awsl-https-443.sock");
+        assert_eq!(passthrough_socket("10.0.0.1:443"), "unix:/run/awsl-https-10_0_0_1_443.sock");
+        assert_eq!(passthrough_socket("[::]:443"), "unix:/run/awsl-https-___443.sock");
+    }
+}
@@ -0,0 +1,73 @@
+//! Stub descriptors and builders shared by the unit tests of `core`.
+
+use std::sync::Arc;
+
+use super::error::Result;
+use super::interface::{BackendDescriptor, ServerInterface, TlsDescriptor, WebServerInstance};
+use super::stream::StreamDescriptor;
+
+/// Renders and passes streams to its key, so routes tell apart by it.
+#[derive(Debug)]
+pub(crate) struct NullBackend {
+    pub key: String,
+}
+
+impl BackendDescriptor for NullBackend {
+    fn get_key(&self) -> String {
+        self.key.clone()
+    }
+    fn to_backend_config(&self) -> Result<String> {
+        Ok(format!("return 200 \"{}\";", self.key))
+    }
+}
+
+impl StreamDescriptor for NullBackend {
+    fn get_pass_target(&self) -> Result<String> {
+        Ok(self.key.clone())
+    }
+}
+
+/// Takes whole TLS connections to `10.0.0.1:443`.
+#[derive(Debug)]
+pub(crate) struct NullPassthrough;
+
+impl BackendDescriptor for NullPassthrough {
+    fn get_key(&self) -> String {
+        "passthrough".to_owned()
+    }
+    fn to_backend_config(&self) -> Result<String> {
+        Ok(String::new())
+    }
+    fn as_passthrough(&self) -> Option<Arc<dyn StreamDescriptor>> {
+        Some(Arc::new(NullPassthrough))
+    }
+}
+
+impl StreamDescriptor for NullPassthrough {
+    fn get_pass_target(&self) -> Result<String> {
+        Ok("10.0.0.1:443".to_owned())
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct NullTls {
+    pub key: String,
+}
+
+impl TlsDescriptor for NullTls {
+    fn get_key(&self) -> String {
+        self.key.clone()
+    }
+    fn to_tls_config(&self) -> Result<String> {
+        Ok(format!("ssl_certificate {}.pem;", self.key))
+    }
+}
+
+pub(crate) fn hosts(h: &[&str]) -> Vec<String> {
+    h.iter().map(|x| x.to_string()).collect()
+}
+
+/// A route of `host` on `interface` served by a `NullBackend` keyed `key`.
+pub(crate) fn instance(host: &[&str], interface: Vec<ServerInterface>, location: Option<&str>, key: &str) -> WebServerInstance {
+    WebServerInstance::new(hosts(host), interface, location.map(|x| x.to_owned()), Arc::new(NullBackend { key: key.to_owned() }))
+}
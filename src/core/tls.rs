@@ -0,0 +1,141 @@
+use std::path::Path;
+use super::config::{ConfigTls, ConfigTlsCiphers};
+use super::interface::{Error, Result, TlsDescriptor};
+
+const INTERMEDIATE_CIPHERS: &str = "ECDHE-ECDSA-AES128-GCM-SHA256:ECDHE-RSA-AES128-GCM-SHA256:\
+    ECDHE-ECDSA-AES256-GCM-SHA384:ECDHE-RSA-AES256-GCM-SHA384:ECDHE-ECDSA-CHACHA20-POLY1305:\
+    ECDHE-RSA-CHACHA20-POLY1305:DHE-RSA-AES128-GCM-SHA256:DHE-RSA-AES256-GCM-SHA384:DHE-RSA-CHACHA20-POLY1305";
+
+fn on_off(value: bool) -> &'static str {
+    if value { "on" } else { "off" }
+}
+
+/// Rejects values that would end or open an nginx directive when inlined.
+fn directive_value<'a>(tls: &ConfigTls, value: &'a str) -> Result<&'a str> {
+    if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == ';' || c == '{' || c == '}') {
+        return Err(Error::InvalidTls {
+            key: tls.get_key(),
+            message: format!("{:?} cannot be used as a directive value", value),
+        });
+    }
+    Ok(value)
+}
+
+impl TlsDescriptor for ConfigTls {
+    fn get_key(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    fn to_tls_config(&self) -> Result<String> {
+        let mut lines = vec![
+            format!("ssl_certificate {};", directive_value(self, &self.certificate.to_string_lossy())?),
+            format!("ssl_certificate_key {};", directive_value(self, &self.key.to_string_lossy())?),
+        ];
+
+        let mut protocols = self.protocols.iter().map(|p| directive_value(self, p)).collect::<Result<Vec<_>>>()?;
+        if protocols.is_empty() {
+            protocols = match self.ciphers {
+                Some(ConfigTlsCiphers::Modern) => vec!["TLSv1.3"],
+                Some(ConfigTlsCiphers::Intermediate) => vec!["TLSv1.2", "TLSv1.3"],
+                None => vec![],
+            };
+        }
+        if !protocols.is_empty() {
+            lines.push(format!("ssl_protocols {};", protocols.join(" ")));
+        }
+        match self.ciphers {
+            Some(ConfigTlsCiphers::Modern) => {
+                lines.push("ssl_prefer_server_ciphers off;".to_owned());
+            },
+            Some(ConfigTlsCiphers::Intermediate) => {
+                lines.push(format!("ssl_ciphers {};", INTERMEDIATE_CIPHERS));
+                lines.push("ssl_prefer_server_ciphers off;".to_owned());
+            },
+            None => {},
+        }
+
+        if self.ocsp_stapling {
+            lines.push("ssl_stapling on;".to_owned());
+            lines.push("ssl_stapling_verify on;".to_owned());
+        }
+        if let Some(tickets) = self.session_tickets {
+            lines.push(format!("ssl_session_tickets {};", on_off(tickets)));
+        }
+        Ok(lines.join("\n"))
+    }
+
+    fn get_certificate(&self) -> Option<&Path> {
+        Some(&self.certificate)
+    }
+}
+
+/// Refuses every TLS handshake, for catch-all servers without a certificate.
+#[derive(Debug)]
+pub struct RejectHandshake;
+
+impl TlsDescriptor for RejectHandshake {
+    fn get_key(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    fn to_tls_config(&self) -> Result<String> {
+        Ok("ssl_reject_handshake on;".to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(yaml: &str) -> ConfigTls {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn tls_config_minimal() {
+        let tls = parse("{certificate: /etc/ssl/a.pem, key: /etc/ssl/a.key}");
+        assert_eq!(tls.to_tls_config().unwrap(), "ssl_certificate /etc/ssl/a.pem;\nssl_certificate_key /etc/ssl/a.key;");
+    }
+
+    #[test]
+    fn tls_config_options() {
+        let tls = parse("
+certificate: /etc/ssl/a.pem
+key: /etc/ssl/a.key
+ciphers: intermediate
+ocspStapling: true
+sessionTickets: false
+");
+        assert_eq!(tls.to_tls_config().unwrap(), format!("\
+ssl_certificate /etc/ssl/a.pem;
+ssl_certificate_key /etc/ssl/a.key;
+ssl_protocols TLSv1.2 TLSv1.3;
+ssl_ciphers {};
+ssl_prefer_server_ciphers off;
+ssl_stapling on;
+ssl_stapling_verify on;
+ssl_session_tickets off;", INTERMEDIATE_CIPHERS));
+
+        let tls = parse("{certificate: /a.pem, key: /a.key, ciphers: modern, protocols: [TLSv1.2]}");
+        assert_eq!(tls.to_tls_config().unwrap(), "\
+ssl_certificate /a.pem;
+ssl_certificate_key /a.key;
+ssl_protocols TLSv1.2;
+ssl_prefer_server_ciphers off;");
+    }
+
+    #[test]
+    fn tls_config_for_host() {
+        let tls = parse("
+certificate: /a.pem
+key: /a.key
+hosts:
+  b.example.com: {certificate: /b.pem, key: /b.key}
+");
+        let b = tls.for_host("b.example.com");
+        assert_eq!(b.certificate.to_str(), Some("/b.pem"));
+        assert!(b.hosts.is_empty());
+        assert_eq!(tls.for_host("a.example.com").key.to_str(), Some("/a.key"));
+        assert_ne!(b.get_key(), tls.for_host("a.example.com").get_key());
+    }
+}
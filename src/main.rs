@@ -1,26 +1,21 @@
-use std::fs::File;
-use std::path::Path;
-use std::error::Error;
+mod cli;
 
-mod core;
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let path = Path::new("example.yml");
-    let disp = path.display();
-    let f = match File::open(&path) {
-        Err(w) => panic!("couldn't open {}: {}", disp, w),
-        Ok(f) => f,
+    let code = match cli::parse_args(&args) {
+        Ok(cmd) => match cli::run(&cmd) {
+            Ok(code) => code,
+            Err(err) => {
+                eprintln!("awsl: {}", err);
+                cli::EXIT_FAILURE
+            },
+        },
+        Err(msg) => {
+            eprintln!("awsl: {}\n\n{}", msg, cli::USAGE);
+            cli::EXIT_USAGE
+        },
     };
 
-    let cfg: core::config::Config = serde_yaml::from_reader(f)?;
-
-    println!("Regenerated:\n{}\n\n", serde_yaml::to_string(&cfg)?);
-
-    if let Err(err) = core::config::validate(&cfg) {
-        panic!("Configuration error: {:?}", err);
-    }
-
-    println!("Ok!");
-
-    Ok(())
+    std::process::exit(code);
 }
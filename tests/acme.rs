@@ -0,0 +1,362 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use awsl::core::acme::{self, Renewal, RenewOptions};
+use awsl::core::cert::read_certificate;
+use awsl::core::config::{self, Config};
+use awsl::core::interface::{OverwritePolicy, Registry};
+use awsl::core::lower;
+use awsl::core::nginx::NginxHttpConfig;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::digest::{digest, SHA256};
+use ring::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_FIXED};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Response, Server};
+
+/*
+
+A local stand-in for an ACME server: it checks nonces and ES256
+signatures, validates HTTP-01 by reading the token file the client wrote,
+and issues self-signed certificates for the ordered names.
+
+*/
+
+struct Authz {
+    name: String,
+    token: String,
+    status: &'static str,
+}
+
+struct Order {
+    names: Vec<String>,
+    authz: Vec<usize>,
+    status: &'static str,
+    certificate: Option<String>,
+}
+
+#[derive(Default)]
+struct State {
+    base: String,
+    challenges: PathBuf,
+    /// Hosts whose challenge is always rejected.
+    reject: Vec<String>,
+    nonces: HashSet<String>,
+    issued_nonces: usize,
+    bad_nonce_sent: bool,
+    jwk: Option<Value>,
+    authz: Vec<Authz>,
+    orders: Vec<Order>,
+}
+
+fn b64decode(s: &str) -> Vec<u8> {
+    URL_SAFE_NO_PAD.decode(s).unwrap()
+}
+
+fn thumbprint(jwk: &Value) -> String {
+    let canonical = json!({ "crv": jwk["crv"], "kty": jwk["kty"], "x": jwk["x"], "y": jwk["y"] });
+    URL_SAFE_NO_PAD.encode(digest(&SHA256, canonical.to_string().as_bytes()).as_ref())
+}
+
+fn verify(jwk: &Value, message: &str, signature: &str) -> bool {
+    let mut point = vec![4u8];
+    point.extend(b64decode(jwk["x"].as_str().unwrap()));
+    point.extend(b64decode(jwk["y"].as_str().unwrap()));
+    UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, point).verify(message.as_bytes(), &b64decode(signature)).is_ok()
+}
+
+impl State {
+    fn nonce(&mut self) -> String {
+        self.issued_nonces += 1;
+        let nonce = format!("nonce-{}", self.issued_nonces);
+        self.nonces.insert(nonce.clone());
+        nonce
+    }
+
+    fn authz_json(&self, id: usize) -> Value {
+        let a = &self.authz[id];
+        let mut challenge = json!({
+            "type": "http-01",
+            "url": format!("{}/chall/{}", self.base, id),
+            "token": a.token,
+            "status": a.status,
+        });
+        if a.status == "invalid" {
+            challenge["error"] = json!({ "detail": format!("wrong key authorization for {}", a.name) });
+        }
+        json!({ "status": a.status, "identifier": { "type": "dns", "value": a.name }, "challenges": [challenge] })
+    }
+
+    fn order_json(&self, id: usize) -> Value {
+        let o = &self.orders[id];
+        let mut order = json!({
+            "status": o.status,
+            "identifiers": o.names.iter().map(|n| json!({ "type": "dns", "value": n })).collect::<Vec<_>>(),
+            "authorizations": o.authz.iter().map(|a| format!("{}/authz/{}", self.base, a)).collect::<Vec<_>>(),
+            "finalize": format!("{}/finalize/{}", self.base, id),
+        });
+        if o.certificate.is_some() {
+            order["certificate"] = json!(format!("{}/cert/{}", self.base, id));
+        }
+        order
+    }
+
+    /// Returns (status, body, location) for a verified JWS request.
+    fn post(&mut self, path: &str, payload: Option<Value>, jwk: Value) -> (u16, String, Option<String>) {
+        let id = |prefix: &str| path.strip_prefix(prefix).and_then(|i| i.parse::<usize>().ok());
+        if path == "/account" {
+            self.jwk = Some(jwk);
+            return (201, json!({ "status": "valid" }).to_string(), Some(format!("{}/account/1", self.base)));
+        }
+        if path == "/order" {
+            let names: Vec<String> = payload.unwrap()["identifiers"].as_array().unwrap().iter()
+                .map(|i| i["value"].as_str().unwrap().to_owned())
+                .collect();
+            let mut authz = Vec::new();
+            for name in &names {
+                authz.push(self.authz.len());
+                let token = format!("token-{}", self.authz.len());
+                self.authz.push(Authz { name: name.clone(), token, status: "pending" });
+            }
+            self.orders.push(Order { names, authz, status: "pending", certificate: None });
+            let id = self.orders.len() - 1;
+            return (201, self.order_json(id).to_string(), Some(format!("{}/order/{}", self.base, id)));
+        }
+        if let Some(id) = id("/authz/") {
+            return (200, self.authz_json(id).to_string(), None);
+        }
+        if let Some(id) = id("/chall/") {
+            let expected = format!("{}.{}", self.authz[id].token, thumbprint(self.jwk.as_ref().unwrap()));
+            let served = fs::read_to_string(self.challenges.join(&self.authz[id].token)).unwrap_or_default();
+            let rejected = self.reject.contains(&self.authz[id].name);
+            self.authz[id].status = if served == expected && !rejected { "valid" } else { "invalid" };
+            return (200, self.authz_json(id).to_string(), None);
+        }
+        if let Some(id) = id("/finalize/") {
+            assert!(payload.unwrap()["csr"].is_string());
+            if self.orders[id].authz.iter().any(|a| self.authz[*a].status != "valid") {
+                return (403, json!({ "type": "urn:ietf:params:acme:error:orderNotReady", "detail": "not authorized" }).to_string(), None);
+            }
+            let key = rcgen::KeyPair::generate().unwrap();
+            let cert = rcgen::CertificateParams::new(self.orders[id].names.clone()).unwrap().self_signed(&key).unwrap();
+            self.orders[id].certificate = Some(cert.pem());
+            self.orders[id].status = "valid";
+            return (200, self.order_json(id).to_string(), None);
+        }
+        if let Some(id) = id("/order/") {
+            return (200, self.order_json(id).to_string(), None);
+        }
+        if let Some(id) = id("/cert/") {
+            return (200, self.orders[id].certificate.clone().unwrap(), None);
+        }
+        (404, String::new(), None)
+    }
+
+    fn handle(&mut self, method: &Method, path: &str, body: &str) -> (u16, String, Option<String>) {
+        match (method, path) {
+            (Method::Get, "/directory") => return (200, json!({
+                "newNonce": format!("{}/nonce", self.base),
+                "newAccount": format!("{}/account", self.base),
+                "newOrder": format!("{}/order", self.base),
+            }).to_string(), None),
+            (Method::Head, "/nonce") => return (200, String::new(), None),
+            (Method::Post, _) => {},
+            _ => return (404, String::new(), None),
+        }
+
+        let jws: Value = serde_json::from_str(body).unwrap();
+        let (protected, payload, signature) = (jws["protected"].as_str().unwrap(), jws["payload"].as_str().unwrap(), jws["signature"].as_str().unwrap());
+        let header: Value = serde_json::from_slice(&b64decode(protected)).unwrap();
+        let nonce = header["nonce"].as_str().unwrap().to_owned();
+        // the very first request is told to retry, exercising badNonce handling
+        if !self.nonces.remove(&nonce) || !self.bad_nonce_sent {
+            self.bad_nonce_sent = true;
+            return (400, json!({ "type": "urn:ietf:params:acme:error:badNonce", "detail": "stale nonce" }).to_string(), None);
+        }
+        assert_eq!(header["url"].as_str().unwrap(), format!("{}{}", self.base, path));
+        let jwk = match (&header["jwk"], &header["kid"]) {
+            (Value::Object(_), _) => header["jwk"].clone(),
+            (_, Value::String(_)) => self.jwk.clone().unwrap(),
+            _ => panic!("request without jwk or kid"),
+        };
+        assert!(verify(&jwk, &format!("{}.{}", protected, payload), signature), "bad signature for {}", path);
+
+        let payload = if payload.is_empty() { None } else { Some(serde_json::from_slice(&b64decode(payload)).unwrap()) };
+        self.post(path, payload, jwk)
+    }
+}
+
+fn spawn_server(challenges: &Path, reject: &[&str]) -> (String, Arc<Mutex<State>>) {
+    let server = Server::http("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", server.server_addr().to_ip().unwrap());
+    let state = Arc::new(Mutex::new(State {
+        base: base.clone(),
+        challenges: challenges.to_owned(),
+        reject: reject.iter().map(|x| x.to_string()).collect(),
+        ..Default::default()
+    }));
+
+    let shared = state.clone();
+    thread::spawn(move || {
+        for mut request in server.incoming_requests() {
+            let mut body = String::new();
+            request.as_reader().read_to_string(&mut body).unwrap();
+            let mut state = shared.lock().unwrap();
+            let (status, body, location) = state.handle(request.method(), request.url(), &body);
+            let mut response = Response::from_string(body).with_status_code(status)
+                .with_header(Header::from_bytes("Replay-Nonce", state.nonce()).unwrap());
+            if let Some(location) = location {
+                response = response.with_header(Header::from_bytes("Location", location).unwrap());
+            }
+            request.respond(response).unwrap();
+        }
+    });
+    (format!("{}/directory", base), state)
+}
+
+fn state_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("awsl-acme-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn config(directory: &str, state: &Path, hosts: &str) -> Config {
+    let cfg: Config = serde_yaml::from_str(&format!("
+certificates:
+  fallback: {1}
+templates:
+  web:
+    module: http
+    https: enforcing
+    acme:
+      directory: {0}
+      email: admin@example.com
+      state: {1}
+servers:
+  - template: web
+    host: {2}
+    backend:
+      type: proxy
+      target: 127.0.0.1:3000
+", directory, state.display(), hosts)).unwrap();
+    config::validate(&cfg).unwrap();
+    cfg
+}
+
+fn options() -> RenewOptions {
+    RenewOptions { now: 0, poll_interval: Duration::from_millis(10) }
+}
+
+#[test]
+fn acme_issues_and_renders_certificates() {
+    let state = state_dir("issue");
+    let (directory, server) = spawn_server(&state.join("challenges"), &[]);
+    let cfg = config(&directory, &state, "[a.example.com, b.example.com]");
+
+    let results = acme::renew(&cfg, &options()).unwrap();
+    assert_eq!(results.len(), 2);
+    for (host, result) in &results {
+        assert_eq!(result.as_ref().unwrap(), &Renewal::Issued { not_after_date: "4096-01-01".to_owned() });
+        let info = read_certificate(&state.join(host).join("fullchain.pem")).unwrap();
+        assert_eq!(info.names, vec![host.clone()]);
+        assert!(state.join(host).join("privkey.pem").is_file());
+    }
+    assert!(state.join("account.pk8").is_file());
+    assert_eq!(fs::read_dir(state.join("challenges")).unwrap().count(), 0);
+    assert_eq!(server.lock().unwrap().orders.len(), 2);
+
+    // nothing is due on the next run
+    let results = acme::renew(&cfg, &options()).unwrap();
+    assert!(results.iter().all(|(_, r)| matches!(r, Ok(Renewal::Current { .. }))));
+    assert_eq!(server.lock().unwrap().orders.len(), 2);
+
+    let mut reg: Registry = Default::default();
+    lower::lower(&cfg, &mut reg, OverwritePolicy::Error).unwrap();
+    let out = reg.to_nginx_http_config().unwrap();
+    assert!(out.contains(&format!("ssl_certificate {}/a.example.com/fullchain.pem;", state.display())));
+    assert!(out.contains(&format!("ssl_certificate_key {}/b.example.com/privkey.pem;", state.display())));
+    assert!(out.contains(&format!("location /.well-known/acme-challenge/ {{\n            default_type text/plain;\n            alias {}/challenges/;", state.display())));
+
+    fs::remove_dir_all(&state).unwrap();
+}
+
+#[test]
+fn acme_renders_before_first_issuance() {
+    let state = state_dir("first");
+    let (directory, _server) = spawn_server(&state.join("challenges"), &[]);
+    let cfg = config(&directory, &state, "[a.example.com, b.example.com]");
+    let render = || {
+        let mut reg: Registry = Default::default();
+        lower::lower(&cfg, &mut reg, OverwritePolicy::Error).unwrap();
+        reg.to_nginx_http_config().unwrap()
+    };
+
+    // nginx has to load the first config to serve the challenge
+    assert!(cfg.uses_self_signed());
+    assert_eq!(acme::ensure_self_signed(&cfg).unwrap(), Some(state.join("fallback.pem")));
+    let out = render();
+    assert!(out.contains("server_name a.example.com b.example.com;"));
+    assert!(out.contains(&format!("ssl_certificate {}/fallback.pem;", state.display())));
+    assert!(out.contains(&format!("ssl_certificate_key {}/fallback.key;", state.display())));
+    assert!(!out.contains("fullchain.pem"));
+
+    acme::renew(&cfg, &options()).unwrap();
+    assert!(!cfg.uses_self_signed());
+    let out = render();
+    assert!(out.contains(&format!("ssl_certificate {}/a.example.com/fullchain.pem;", state.display())));
+    assert!(!out.contains("fallback.pem"));
+
+    fs::remove_dir_all(&state).unwrap();
+}
+
+#[test]
+fn acme_reports_failed_hosts() {
+    let state = state_dir("reject");
+    let (directory, _server) = spawn_server(&state.join("challenges"), &["bad.example.com"]);
+    let cfg = config(&directory, &state, "[bad.example.com, good.example.com]");
+
+    let results = acme::renew(&cfg, &options()).unwrap();
+    assert_eq!(results[0].0, "bad.example.com");
+    assert_eq!(results[0].1.as_ref().unwrap_err().to_string(),
+        "ACME: authorization of bad.example.com failed: wrong key authorization for bad.example.com");
+    assert!(!state.join("bad.example.com").exists());
+    assert!(matches!(results[1], (ref host, Ok(Renewal::Issued { .. })) if host == "good.example.com"));
+
+    fs::remove_dir_all(&state).unwrap();
+}
+
+#[test]
+fn acme_creates_self_signed_fallback() {
+    let state = state_dir("fallback");
+    let cfg: Config = serde_yaml::from_str(&format!("
+catchAll:
+  type: page
+certificates:
+  fallback: {}
+templates:
+  web:
+    module: http
+    https: only
+    tls:
+      certificate: tests/fixtures/tls/example.com.pem
+      key: tests/fixtures/tls/example.com.key
+servers:
+  - template: web
+    host: example.com
+    backend: /srv/www
+", state.display())).unwrap();
+    config::validate(&cfg).unwrap();
+
+    let created = acme::ensure_self_signed(&cfg).unwrap();
+    assert_eq!(created, Some(state.join("fallback.pem")));
+    assert_eq!(read_certificate(&state.join("fallback.pem")).unwrap().names, vec!["fallback.invalid".to_owned()]);
+    assert!(state.join("fallback.key").is_file());
+    // kept on the next run
+    assert_eq!(acme::ensure_self_signed(&cfg).unwrap(), None);
+
+    fs::remove_dir_all(&state).unwrap();
+}
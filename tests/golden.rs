@@ -0,0 +1,105 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Renders `tests/golden/<name>.yml` and compares it with `<name>.conf`.
+/// Paths inside the configs are relative to the crate root.
+fn golden(name: &str) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let dir = root.join("tests/golden");
+    let output = Command::new(env!("CARGO_BIN_EXE_awsl"))
+        .current_dir(root)
+        .arg("render")
+        .arg(dir.join(format!("{}.yml", name)))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "render {} failed: {}", name, String::from_utf8_lossy(&output.stderr));
+
+    let expected = fs::read_to_string(dir.join(format!("{}.conf", name))).unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+}
+
+#[test]
+fn golden_https_only() {
+    golden("only");
+}
+
+#[test]
+fn golden_https_compatible() {
+    golden("compatible");
+}
+
+#[test]
+fn golden_https_disabled() {
+    golden("disabled");
+}
+
+#[test]
+fn golden_https_enforcing() {
+    golden("enforcing");
+}
+
+#[test]
+fn golden_https_hsts() {
+    golden("hsts");
+}
+
+#[test]
+fn golden_tls() {
+    golden("tls");
+}
+
+#[test]
+fn golden_upstream() {
+    golden("upstream");
+}
+
+#[test]
+fn golden_proxy_options() {
+    golden("proxy");
+}
+
+#[test]
+fn golden_fastcgi() {
+    golden("fastcgi");
+}
+
+#[test]
+fn golden_grpc_uwsgi_scgi() {
+    golden("grpc");
+}
+
+#[test]
+fn golden_static_files() {
+    golden("static");
+}
+
+#[test]
+fn golden_rewrite() {
+    golden("rewrite");
+}
+
+#[test]
+fn golden_stream() {
+    golden("stream");
+}
+
+#[test]
+fn golden_passthrough() {
+    golden("passthrough");
+}
+
+#[test]
+fn golden_addresses() {
+    golden("addresses");
+}
+
+#[test]
+fn golden_listen_options() {
+    golden("listen");
+}
+
+#[test]
+fn golden_catch_all() {
+    golden("catchall");
+}